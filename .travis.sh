@@ -1,3 +1,4 @@
 #!/bin/sh
 
-cargo test --verbose --release
+cargo test --verbose --release --all
+cargo test --verbose --release --all --all-features
@@ -0,0 +1,246 @@
+#[macro_use]
+extern crate criterion;
+extern crate bitbuf;
+
+use bitbuf::{BitBuf, BitRead, BitWrite, FromBitBuf, Result, WriteToBitBuf};
+use criterion::{black_box, Criterion};
+
+struct BenchPerson {
+    first_name: String,
+    last_name: String,
+    age: i8,
+    alive: bool,
+    weight: i16,
+}
+
+impl WriteToBitBuf for BenchPerson {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        buf.write_string(&self.first_name)?;
+        buf.write_string(&self.last_name)?;
+        buf.write_i8(self.age)?;
+        buf.write_bool(self.alive)?;
+        buf.write_i16(self.weight)
+    }
+}
+
+impl FromBitBuf for BenchPerson {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<BenchPerson> {
+        Ok(BenchPerson {
+            first_name: buf.read_string()?,
+            last_name: buf.read_string()?,
+            age: buf.read_i8()?,
+            alive: buf.read_bool()?,
+            weight: buf.read_i16()?,
+        })
+    }
+}
+
+fn bench_person() -> BenchPerson {
+    BenchPerson {
+        first_name: String::from("John"),
+        last_name: String::from("Johnson"),
+        age: 47,
+        alive: true,
+        weight: 203,
+    }
+}
+
+fn benchperson_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    let person = bench_person();
+    c.bench_function("benchperson_write1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..63 {
+            person.write_to_bitbuf(&mut buf).unwrap();
+        }
+    }));
+}
+
+fn benchperson_read1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    let person = bench_person();
+    for _ in 0..63 {
+        person.write_to_bitbuf(&mut buf).unwrap();
+    }
+    c.bench_function("benchperson_read1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..63 {
+            black_box(BenchPerson::from_bitbuf(&mut buf).unwrap());
+        }
+    }));
+}
+
+fn bitbuf_create_bench(c: &mut Criterion) {
+    c.bench_function("bitbuf_create", |b| b.iter(|| {
+        black_box(BitBuf::with_len(1400));
+    }));
+}
+
+fn byte_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    c.bench_function("byte_write1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..1400 {
+            buf.write_u8(black_box(240)).unwrap();
+        }
+    }));
+}
+
+fn byte_read1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    for _ in 0..1400 {
+        buf.write_u8(240).unwrap();
+    }
+    c.bench_function("byte_read1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..1400 {
+            black_box(buf.read_u8().unwrap());
+        }
+    }));
+}
+
+fn string_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    c.bench_function("string_write1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..50 {
+            buf.write_string(black_box("This is a string. Woo!!!")).unwrap();
+        }
+    }));
+}
+
+fn string_read1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    for _ in 0..50 {
+        buf.write_string("This is a string. Woo!!!").unwrap();
+    }
+    c.bench_function("string_read1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..50 {
+            black_box(buf.read_string().unwrap());
+        }
+    }));
+}
+
+fn slice_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    let blob = [0xA5; 1399];
+    c.bench_function("slice_write1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        buf.write_u8_slice(black_box(&blob)).unwrap();
+    }));
+    c.bench_function("slice_unaligned_write1400", |b| b.iter(|| {
+        buf.set_bit_pos(3).unwrap();
+        buf.write_u8_slice(black_box(&blob)).unwrap();
+    }));
+}
+
+fn slice_read1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    let mut blob = [0; 1399];
+    c.bench_function("slice_read1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        buf.read_into(black_box(&mut blob)).unwrap();
+    }));
+    c.bench_function("slice_unaligned_read1400", |b| b.iter(|| {
+        buf.set_bit_pos(3).unwrap();
+        buf.read_into(black_box(&mut blob)).unwrap();
+    }));
+}
+
+fn i32_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    c.bench_function("i32_write1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..350 {
+            buf.write_i32(black_box(123239012)).unwrap();
+        }
+    }));
+}
+
+fn i64_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    c.bench_function("i64_write1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..175 {
+            buf.write_i64(black_box(12352390123458)).unwrap();
+        }
+    }));
+}
+
+fn i64_read1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    for _ in 0..175 {
+        buf.write_i64(12352390123458).unwrap();
+    }
+    c.bench_function("i64_read1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..175 {
+            black_box(buf.read_i64().unwrap());
+        }
+    }));
+}
+
+fn i64_unaligned_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    c.bench_function("i64_unaligned_write1400", |b| b.iter(|| {
+        buf.set_bit_pos(3).unwrap();
+        for _ in 0..174 {
+            buf.write_i64(black_box(12352390123458)).unwrap();
+        }
+    }));
+}
+
+fn f32_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    c.bench_function("f32_write1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..350 {
+            buf.write_f32(black_box(123.23901f32)).unwrap();
+        }
+    }));
+}
+
+fn f64_write1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    c.bench_function("f64_write1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..175 {
+            buf.write_f64(black_box(1235.2390123458f64)).unwrap();
+        }
+    }));
+}
+
+fn f64_read1400_bench(c: &mut Criterion) {
+    let mut buf = BitBuf::with_len(1400);
+    for _ in 0..175 {
+        buf.write_f64(1235.2390123458f64).unwrap();
+    }
+    c.bench_function("f64_read1400", |b| b.iter(|| {
+        buf.rewind().unwrap();
+        for _ in 0..175 {
+            black_box(buf.read_f64().unwrap());
+        }
+    }));
+}
+
+criterion_group!(
+    benches,
+    benchperson_write1400_bench,
+    benchperson_read1400_bench,
+    bitbuf_create_bench,
+    byte_write1400_bench,
+    byte_read1400_bench,
+    string_write1400_bench,
+    string_read1400_bench,
+    slice_write1400_bench,
+    slice_read1400_bench,
+    i32_write1400_bench,
+    i64_write1400_bench,
+    i64_read1400_bench,
+    i64_unaligned_write1400_bench,
+    f32_write1400_bench,
+    f64_write1400_bench,
+    f64_read1400_bench
+);
+criterion_main!(benches);
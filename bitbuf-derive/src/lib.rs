@@ -0,0 +1,426 @@
+//! Derive macros for `bitbuf::WriteToBitBuf` and `bitbuf::FromBitBuf`.
+//!
+//! Fields are written in declaration order. Enums write the variant
+//! index in as few bits as can hold every variant, followed by the
+//! variant's fields. Fields accept these attributes:
+//!
+//! * `#[bitbuf(bits = 7)]` writes an integer in 7 bits. Signed integers
+//!   are sign-extended when read, and rejected when written if they do
+//!   not fit.
+//! * `#[bitbuf(range = "0..=100")]` writes an integer as its offset from
+//!   the start of the range, in as few bits as the range needs. The range
+//!   must fit the field's type, and values outside it are rejected when
+//!   writing and reading.
+//! * `#[bitbuf(skip)]` writes nothing and reads `Default::default()`.
+//!
+//! Structs also get `write_delta` and `read_delta`, which encode each
+//! field against the matching field of the baseline. Fields with `bits`
+//! or `range` write a changed bit, then the value only if it changed.
+//! Enums keep the default, which writes the whole value.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro2::{Span, TokenStream};
+use syn::{Attribute, Data, DeriveInput, Error, Fields, Ident, LitInt, LitStr, Member, Type};
+
+#[proc_macro_derive(WriteToBitBuf, attributes(bitbuf))]
+pub fn derive_write(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    expand_write(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(FromBitBuf, attributes(bitbuf))]
+pub fn derive_read(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    expand_read(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// How a single field is encoded, taken from its `#[bitbuf]` attributes.
+enum Encoding {
+    Default,
+    Skip,
+    Bits(u8),
+    Range(i128, i128),
+}
+
+struct Field<'a> {
+    member: Member,
+    ty: &'a Type,
+    encoding: Encoding,
+}
+
+const INTEGERS: &[&str] = &["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64"];
+
+fn expand_write(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in input.generics.type_params() {
+        let ident = &param.ident;
+        generics.make_where_clause().predicates.push(syn::parse_quote!(#ident: ::bitbuf::WriteToBitBuf));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let mut delta = quote!();
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let fields = parse_fields(&data.fields)?;
+            let writes = fields.iter().map(|f| {
+                let member = &f.member;
+                write_field(f, quote!(self.#member))
+            });
+            let deltas = fields.iter().map(write_field_delta);
+            delta = quote! {
+                fn write_delta<__W: ::bitbuf::BitWrite>(&self, baseline: &Self, buf: &mut __W) -> ::bitbuf::Result<()> {
+                    #(#deltas)*
+                    Ok(())
+                }
+            };
+            quote!(#(#writes)*)
+        },
+        Data::Enum(ref data) => {
+            let tag_bits = bits_required(data.variants.len().saturating_sub(1) as u128);
+            let mut arms = Vec::new();
+            for (index, variant) in data.variants.iter().enumerate() {
+                let index = index as u32;
+                let ident = &variant.ident;
+                let fields = parse_fields(&variant.fields)?;
+                let bindings: Vec<Ident> = (0..fields.len()).map(binding).collect();
+                let patterns = fields.iter().zip(&bindings).map(|(f, b)| {
+                    let member = &f.member;
+                    match f.encoding {
+                        Encoding::Skip => quote!(#member: _),
+                        _ => quote!(#member: ref #b),
+                    }
+                });
+                let writes = fields.iter().zip(&bindings).map(|(f, b)| write_field(f, quote!(*#b)));
+                arms.push(quote! {
+                    #name::#ident { #(#patterns,)* } => {
+                        ::bitbuf::BitWrite::write_u32_part(buf, #index, #tag_bits)?;
+                        #(#writes)*
+                    }
+                });
+            }
+            if arms.is_empty() {
+                return Err(Error::new_spanned(input, "cannot derive WriteToBitBuf for an enum with no variants"));
+            }
+            quote! {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        },
+        Data::Union(_) => return Err(Error::new_spanned(input, "cannot derive WriteToBitBuf for a union")),
+    };
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl #impl_generics ::bitbuf::WriteToBitBuf for #name #ty_generics #where_clause {
+            fn write_to_bitbuf<__W: ::bitbuf::BitWrite>(&self, buf: &mut __W) -> ::bitbuf::Result<()> {
+                #body
+                Ok(())
+            }
+
+            #delta
+        }
+    })
+}
+
+fn expand_read(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in input.generics.type_params() {
+        let ident = &param.ident;
+        generics.make_where_clause().predicates.push(syn::parse_quote!(#ident: ::bitbuf::FromBitBuf));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let mut delta = quote!();
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let fields = parse_fields(&data.fields)?;
+            let bindings: Vec<Ident> = (0..fields.len()).map(binding).collect();
+            let reads = fields.iter().map(read_field_delta);
+            let members = fields.iter().map(|f| &f.member);
+            delta = quote! {
+                fn read_delta<__R: ::bitbuf::BitRead>(baseline: &Self, buf: &mut __R) -> ::bitbuf::Result<Self> {
+                    #(let #bindings = #reads;)*
+                    Ok(#name { #(#members: #bindings,)* })
+                }
+            };
+            construct(quote!(#name), &fields)
+        },
+        Data::Enum(ref data) => {
+            let tag_bits = bits_required(data.variants.len().saturating_sub(1) as u128);
+            let mut arms = Vec::new();
+            for (index, variant) in data.variants.iter().enumerate() {
+                let index = index as u32;
+                let ident = &variant.ident;
+                let fields = parse_fields(&variant.fields)?;
+                let value = construct(quote!(#name::#ident), &fields);
+                arms.push(quote!(#index => { #value }));
+            }
+            if arms.is_empty() {
+                return Err(Error::new_spanned(input, "cannot derive FromBitBuf for an enum with no variants"));
+            }
+            quote! {
+                match ::bitbuf::BitRead::read_u32_part(buf, #tag_bits)? {
+                    #(#arms)*
+                    _ => Err(::bitbuf::BitBufError::ValueOutOfRange),
+                }
+            }
+        },
+        Data::Union(_) => return Err(Error::new_spanned(input, "cannot derive FromBitBuf for a union")),
+    };
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl #impl_generics ::bitbuf::FromBitBuf for #name #ty_generics #where_clause {
+            fn from_bitbuf<__R: ::bitbuf::BitRead>(buf: &mut __R) -> ::bitbuf::Result<Self> {
+                #body
+            }
+
+            #delta
+        }
+    })
+}
+
+/// Reads every field in order, then builds `path` from them.
+fn construct(path: TokenStream, fields: &[Field]) -> TokenStream {
+    let bindings: Vec<Ident> = (0..fields.len()).map(binding).collect();
+    let reads = fields.iter().map(read_field);
+    let members = fields.iter().map(|f| &f.member);
+    quote! {
+        #(let #bindings = #reads;)*
+        Ok(#path { #(#members: #bindings,)* })
+    }
+}
+
+fn binding(index: usize) -> Ident {
+    Ident::new(&format!("__field{}", index), Span::call_site())
+}
+
+fn write_field(field: &Field, place: TokenStream) -> TokenStream {
+    match field.encoding {
+        Encoding::Skip => quote!(),
+        Encoding::Bits(bits) => {
+            let method = Ident::new(&format!("write_{}_part", primitive(field.ty).unwrap()), Span::call_site());
+            quote!(::bitbuf::BitWrite::#method(buf, #place, #bits)?;)
+        },
+        Encoding::Range(min, max) => {
+            let bits = bits_required((max - min) as u128);
+            quote! {
+                let __value = #place as i128;
+                if !(#min..=#max).contains(&__value) {
+                    return Err(::bitbuf::BitBufError::ValueOutOfRange);
+                }
+                ::bitbuf::BitWrite::write_u64_part(buf, (__value - #min) as u64, #bits)?;
+            }
+        },
+        Encoding::Default => match primitive(field.ty) {
+            Some("String") => quote!(::bitbuf::BitWrite::write_string(buf, &#place)?;),
+            Some(ty) => {
+                let method = Ident::new(&format!("write_{}", ty), Span::call_site());
+                quote!(::bitbuf::BitWrite::#method(buf, #place)?;)
+            },
+            None => quote!(::bitbuf::WriteToBitBuf::write_to_bitbuf(&#place, buf)?;),
+        },
+    }
+}
+
+/// Writes the field at `self` as a change from the one at `baseline`.
+fn write_field_delta(field: &Field) -> TokenStream {
+    let member = &field.member;
+    match field.encoding {
+        Encoding::Skip => quote!(),
+        Encoding::Bits(_) | Encoding::Range(..) => {
+            let write = write_field(field, quote!(self.#member));
+            quote! {
+                if self.#member == baseline.#member {
+                    ::bitbuf::BitWrite::write_bool(buf, false)?;
+                } else {
+                    ::bitbuf::BitWrite::write_bool(buf, true)?;
+                    #write
+                }
+            }
+        },
+        Encoding::Default => {
+            quote!(::bitbuf::WriteToBitBuf::write_delta(&self.#member, &baseline.#member, buf)?;)
+        },
+    }
+}
+
+fn read_field_delta(field: &Field) -> TokenStream {
+    let member = &field.member;
+    let ty = field.ty;
+    match field.encoding {
+        Encoding::Skip => quote!(::std::default::Default::default()),
+        Encoding::Bits(_) | Encoding::Range(..) => {
+            let read = read_field(field);
+            quote! {
+                if ::bitbuf::BitRead::read_bool(buf)? {
+                    #read
+                } else {
+                    baseline.#member
+                }
+            }
+        },
+        Encoding::Default => quote!(<#ty as ::bitbuf::FromBitBuf>::read_delta(&baseline.#member, buf)?),
+    }
+}
+
+fn read_field(field: &Field) -> TokenStream {
+    let ty = field.ty;
+    match field.encoding {
+        Encoding::Skip => quote!(::std::default::Default::default()),
+        Encoding::Bits(bits) => {
+            let method = Ident::new(&format!("read_{}_part", primitive(ty).unwrap()), Span::call_site());
+            quote!(::bitbuf::BitRead::#method(buf, #bits)?)
+        },
+        Encoding::Range(min, max) => {
+            let bits = bits_required((max - min) as u128);
+            quote! {{
+                let __value = #min + ::bitbuf::BitRead::read_u64_part(buf, #bits)? as i128;
+                if __value > #max {
+                    return Err(::bitbuf::BitBufError::ValueOutOfRange);
+                }
+                __value as #ty
+            }}
+        },
+        Encoding::Default => match primitive(ty) {
+            Some(ty) => {
+                let method = Ident::new(&format!("read_{}", ty.to_lowercase()), Span::call_site());
+                quote!(::bitbuf::BitRead::#method(buf)?)
+            },
+            None => quote!(<#ty as ::bitbuf::FromBitBuf>::from_bitbuf(buf)?),
+        },
+    }
+}
+
+/// Returns the name of a type that BitBuf reads and writes directly.
+fn primitive(ty: &Type) -> Option<&'static str> {
+    const PRIMITIVES: &[&str] = &[
+        "bool", "u8", "i8", "u16", "i16", "u32", "i32", "u64", "i64", "f32", "f64", "String",
+    ];
+    let path = match *ty {
+        Type::Path(ref path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let ident = path.get_ident()?;
+    PRIMITIVES.iter().find(|p| ident == *p).cloned()
+}
+
+fn parse_fields(fields: &Fields) -> syn::Result<Vec<Field<'_>>> {
+    let mut parsed = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let member = match field.ident {
+            Some(ref ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(index.into()),
+        };
+        let encoding = parse_encoding(&field.attrs)?;
+        check_encoding(&encoding, &field.ty)?;
+        parsed.push(Field { member, ty: &field.ty, encoding });
+    }
+    Ok(parsed)
+}
+
+fn parse_encoding(attrs: &[Attribute]) -> syn::Result<Encoding> {
+    let mut encoding = Encoding::Default;
+    for attr in attrs {
+        if !attr.path().is_ident("bitbuf") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if let Encoding::Default = encoding {
+            } else {
+                return Err(meta.error("only one of `bits`, `range` or `skip` may be given"));
+            }
+            if meta.path.is_ident("skip") {
+                encoding = Encoding::Skip;
+            } else if meta.path.is_ident("bits") {
+                let lit: LitInt = meta.value()?.parse()?;
+                encoding = Encoding::Bits(lit.base10_parse()?);
+            } else if meta.path.is_ident("range") {
+                let lit: LitStr = meta.value()?.parse()?;
+                let (min, max) = parse_range(&lit)?;
+                encoding = Encoding::Range(min, max);
+            } else {
+                return Err(meta.error("expected `bits`, `range` or `skip`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(encoding)
+}
+
+/// Parses `"min..=max"` or `"min..end"` into inclusive bounds.
+fn parse_range(lit: &LitStr) -> syn::Result<(i128, i128)> {
+    let value = lit.value().replace('_', "");
+    let (start, end, inclusive) = if let Some(i) = value.find("..=") {
+        (&value[..i], &value[i + 3..], true)
+    } else if let Some(i) = value.find("..") {
+        (&value[..i], &value[i + 2..], false)
+    } else {
+        return Err(Error::new_spanned(lit, "expected a range such as \"0..=100\""));
+    };
+    let parse = |s: &str| s.trim().parse::<i128>().map_err(|_| Error::new_spanned(lit, "range bounds must be integers"));
+    let min = parse(start)?;
+    let max = if inclusive { parse(end)? } else { parse(end)? - 1 };
+    if min > max {
+        return Err(Error::new_spanned(lit, "range is empty"));
+    }
+    if bits_required((max - min) as u128) > 64 {
+        return Err(Error::new_spanned(lit, "range needs more than 64 bits"));
+    }
+    Ok((min, max))
+}
+
+fn check_encoding(encoding: &Encoding, ty: &Type) -> syn::Result<()> {
+    match *encoding {
+        Encoding::Bits(bits) => match primitive(ty) {
+            Some(name) if INTEGERS.contains(&name) => {
+                let width: u8 = name[1..].parse().unwrap();
+                if bits == 0 || bits > width {
+                    return Err(Error::new_spanned(ty, format!("bits must be between 1 and {} for {}", width, name)));
+                }
+                Ok(())
+            },
+            _ => Err(Error::new_spanned(ty, "`bits` is only supported on integer fields")),
+        },
+        Encoding::Range(min, max) => match primitive(ty) {
+            Some(name) if INTEGERS.contains(&name) => {
+                let width: u32 = name[1..].parse().unwrap();
+                let (type_min, type_max) = if name.starts_with('i') {
+                    (-(1i128 << (width - 1)), (1i128 << (width - 1)) - 1)
+                } else {
+                    (0, (1i128 << width) - 1)
+                };
+                if min < type_min || max > type_max {
+                    return Err(Error::new_spanned(ty, format!("range must lie within {}::MIN..={}::MAX", name, name)));
+                }
+                Ok(())
+            },
+            _ => Err(Error::new_spanned(ty, "`range` is only supported on integer fields")),
+        },
+        Encoding::Default | Encoding::Skip => Ok(()),
+    }
+}
+
+/// The number of bits needed to hold every value in `0..=span`.
+fn bits_required(span: u128) -> u8 {
+    (128 - span.leading_zeros()) as u8
+}
+
+#[test]
+fn range_outside_type_errors() {
+    let u8_ty: Type = syn::parse_quote!(u8);
+    let i8_ty: Type = syn::parse_quote!(i8);
+    assert!(check_encoding(&Encoding::Range(0, 255), &u8_ty).is_ok());
+    assert!(check_encoding(&Encoding::Range(0, 1000), &u8_ty).is_err());
+    assert!(check_encoding(&Encoding::Range(-5, 5), &u8_ty).is_err());
+    assert!(check_encoding(&Encoding::Range(-128, 127), &i8_ty).is_ok());
+    assert!(check_encoding(&Encoding::Range(-129, 0), &i8_ty).is_err());
+}
@@ -0,0 +1,179 @@
+//! Checksums over bit ranges. A range is fed to the checksum a byte at
+//! a time, as `BitBuf::finish` would return it had the range started at
+//! bit 0: whole bytes as they are, then any trailing partial byte zero
+//! padded in the buffer's bit order. Ranges starting on a byte boundary
+//! therefore match a checksum of the plain bytes.
+
+use std::ops::Range;
+
+use error::{BitBufError, Result};
+use raw::{self, BitOrder};
+
+const CRC8_TABLE: [u8; 256] = crc8_table();
+const CRC16_TABLE: [u16; 256] = crc16_table();
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// CRC-8/SMBUS: polynomial 0x07, initial value 0, not reflected.
+pub fn crc8(buf: &[u8], range: Range<usize>, order: BitOrder) -> u8 {
+    let mut crc = 0u8;
+    for_each_byte(buf, range, order, |byte| {
+        crc = CRC8_TABLE[(crc ^ byte) as usize];
+    });
+    crc
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF, not
+/// reflected.
+pub fn crc16(buf: &[u8], range: Range<usize>, order: BitOrder) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for_each_byte(buf, range, order, |byte| {
+        crc = (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize];
+    });
+    crc
+}
+
+/// CRC-32 as used by zlib and Ethernet: reflected polynomial 0xEDB88320,
+/// initial value and final xor 0xFFFFFFFF.
+pub fn crc32(buf: &[u8], range: Range<usize>, order: BitOrder) -> u32 {
+    let mut crc = !0u32;
+    for_each_byte(buf, range, order, |byte| {
+        crc = (crc >> 8) ^ CRC32_TABLE[(crc as u8 ^ byte) as usize];
+    });
+    !crc
+}
+
+/// Adler-32 as used by zlib.
+pub fn adler32(buf: &[u8], range: Range<usize>, order: BitOrder) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for_each_byte(buf, range, order, |byte| {
+        a = (a + byte as u32) % MOD;
+        b = (b + a) % MOD;
+    });
+    (b << 16) | a
+}
+
+/// Checks a CRC-32 in the 32 bits before `end` against the bits before
+/// it, as appended by `BitBuf::append_crc32`. Data that does not end on
+/// a byte boundary cannot hold one and fails the check.
+pub fn verify_crc32(buf: &[u8], end: usize, order: BitOrder) -> Result<bool> {
+    if end < 32 {
+        return Err(BitBufError::OutOfBounds { needed: 32, remaining: end });
+    }
+    if end & 0x7 != 0 {
+        return Ok(false);
+    }
+    let expected = raw::read_bits(buf, end - 32, 32, order) as u32;
+    Ok(crc32(buf, 0..end - 32, order) == expected)
+}
+
+/// Checks that `range` is ordered and ends within `size` bits.
+pub fn check_range(range: &Range<usize>, size: usize) -> Result<()> {
+    if range.start > range.end || range.end > size {
+        return Err(BitBufError::OutOfBounds {
+            needed: range.end.saturating_sub(range.start),
+            remaining: size.saturating_sub(range.start),
+        });
+    }
+    Ok(())
+}
+
+/// Calls `f` with each byte of `range`. Does not check bounds.
+fn for_each_byte<F: FnMut(u8)>(buf: &[u8], range: Range<usize>, order: BitOrder, mut f: F) {
+    let mut pos = range.start;
+    if pos & 0x7 == 0 {
+        let whole = (range.end - pos) / 8;
+        for &byte in &buf[pos / 8..pos / 8 + whole] {
+            f(byte);
+        }
+        pos += whole * 8;
+    }
+    while range.end - pos >= 8 {
+        f(raw::read_bits(buf, pos, 8, order) as u8);
+        pos += 8;
+    }
+    let tail = range.end - pos;
+    if tail > 0 {
+        let value = raw::read_bits(buf, pos, tail as u8, order) as u8;
+        f(match order {
+            BitOrder::LsbFirst => value,
+            BitOrder::MsbFirst => value << (8 - tail),
+        });
+    }
+}
+
+const fn crc8_table() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[test]
+fn checksums_match_check_values() {
+    let data = b"123456789";
+    let all = 0..data.len() * 8;
+    assert!(crc8(data, all.clone(), BitOrder::LsbFirst) == 0xF4);
+    assert!(crc16(data, all.clone(), BitOrder::LsbFirst) == 0x29B1);
+    assert!(crc32(data, all.clone(), BitOrder::LsbFirst) == 0xCBF43926);
+    assert!(adler32(data, all.clone(), BitOrder::LsbFirst) == 0x091E01DE);
+    assert!(crc32(data, all, BitOrder::MsbFirst) == 0xCBF43926);
+    assert!(crc32(&[], 0..0, BitOrder::LsbFirst) == 0);
+}
+
+#[test]
+fn checksum_ranges_equal() {
+    for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+        // The same 20 bits written at bit 0 and at bit 5.
+        let mut aligned = [0u8; 4];
+        let mut shifted = [0u8; 4];
+        raw::write_bits(&mut aligned, 0, 0xABCDE, 20, order);
+        raw::write_bits(&mut shifted, 0, 0x1F, 5, order);
+        raw::write_bits(&mut shifted, 5, 0xABCDE, 20, order);
+        assert!(crc32(&aligned, 0..20, order) == crc32(&shifted, 5..25, order));
+        assert!(crc16(&aligned, 0..20, order) == crc16(&aligned[..3], 0..24, order));
+    }
+    assert!(check_range(&(4..33), 32).is_err());
+    assert!(check_range(&Range { start: 9, end: 8 }, 32).is_err());
+}
@@ -0,0 +1,152 @@
+use error::{BitBufError, Result};
+use raw::LengthPrefix;
+use read::BitRead;
+use write::BitWrite;
+
+/// How the serde integration encodes integers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntEncoding {
+    /// Every integer takes its full width, e.g. 32 bits for a u32.
+    Fixed,
+    /// Integers take 8 bits per 7 bits of magnitude (LEB128). Signed
+    /// integers are zigzag encoded first so small negatives stay small.
+    Varint,
+}
+
+/// Encoding options for `to_bitbuf_with` and `from_bitbuf_with`. Both
+/// sides must use the same configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub int_encoding: IntEncoding,
+    /// How the lengths of strings, byte arrays, sequences and maps are
+    /// written.
+    pub length_prefix: LengthPrefix,
+    /// The longest length accepted. Longer lengths are rejected when
+    /// writing, and when reading before anything is allocated.
+    pub max_len: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            int_encoding: IntEncoding::Fixed,
+            length_prefix: LengthPrefix::U32,
+            max_len: usize::MAX,
+        }
+    }
+}
+
+impl Config {
+
+    pub(crate) fn write_uint<W: BitWrite>(&self, buf: &mut W, value: u64, bits: u8) -> Result<()> {
+        match self.int_encoding {
+            IntEncoding::Fixed => buf.write_u64_part(value, bits),
+            IntEncoding::Varint => buf.write_var_u64(value),
+        }
+    }
+
+    pub(crate) fn read_uint<R: BitRead>(&self, buf: &mut R, bits: u8) -> Result<u64> {
+        match self.int_encoding {
+            IntEncoding::Fixed => buf.read_u64_part(bits),
+            IntEncoding::Varint => {
+                let value = buf.read_var_u64()?;
+                if bits < 64 && value >> bits != 0 {
+                    return Err(BitBufError::ValueOutOfRange);
+                }
+                Ok(value)
+            },
+        }
+    }
+
+    pub(crate) fn write_sint<W: BitWrite>(&self, buf: &mut W, value: i64, bits: u8) -> Result<()> {
+        match self.int_encoding {
+            IntEncoding::Fixed => buf.write_i64_part(value, bits),
+            IntEncoding::Varint => buf.write_var_i64(value),
+        }
+    }
+
+    pub(crate) fn read_sint<R: BitRead>(&self, buf: &mut R, bits: u8) -> Result<i64> {
+        match self.int_encoding {
+            IntEncoding::Fixed => buf.read_i64_part(bits),
+            IntEncoding::Varint => {
+                let value = buf.read_var_i64()?;
+                let shift = 64 - bits;
+                if (value << shift) >> shift != value {
+                    return Err(BitBufError::ValueOutOfRange);
+                }
+                Ok(value)
+            },
+        }
+    }
+    /// Wraps `buf` so that its lengths use this configuration's prefix
+    /// and maximum, after checking the prefix.
+    pub(crate) fn lengths<'a, B: ?Sized>(&self, buf: &'a mut B) -> Result<Lengths<'a, B>> {
+        self.length_prefix.check()?;
+        Ok(Lengths { buf, length_prefix: self.length_prefix, max_len: self.max_len })
+    }
+}
+
+/// A reader or writer whose `length_prefix()` and `max_len()` come from
+/// a `Config`, so serde lengths go through the same `BitRead` and
+/// `BitWrite` methods as everything else.
+pub(crate) struct Lengths<'a, B: 'a + ?Sized> {
+    buf: &'a mut B,
+    length_prefix: LengthPrefix,
+    max_len: usize,
+}
+
+impl<'a, B: BitWrite + ?Sized> BitWrite for Lengths<'a, B> {
+    fn write_bits(&mut self, value: u64, bits: u8) -> Result<()> {
+        self.buf.write_bits(value, bits)
+    }
+
+    fn reserve_bits(&mut self, bits: usize) -> Result<()> {
+        self.buf.reserve_bits(bits)
+    }
+
+    fn bit_pos(&self) -> usize {
+        BitWrite::bit_pos(self.buf)
+    }
+
+    fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
+    }
+
+    fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    fn write_u8_slice(&mut self, value: &[u8]) -> Result<()> {
+        self.buf.write_u8_slice(value)
+    }
+}
+
+impl<'a, B: BitRead + ?Sized> BitRead for Lengths<'a, B> {
+    fn read_bits(&mut self, bits: u8) -> Result<u64> {
+        self.buf.read_bits(bits)
+    }
+
+    fn remaining_bits(&self) -> usize {
+        self.buf.remaining_bits()
+    }
+
+    fn bit_pos(&self) -> usize {
+        BitRead::bit_pos(self.buf)
+    }
+
+    fn set_bit_pos(&mut self, pos: usize) -> Result<()> {
+        self.buf.set_bit_pos(pos)
+    }
+
+    fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
+    }
+
+    fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    fn read_into(&mut self, out: &mut [u8]) -> Result<()> {
+        self.buf.read_into(out)
+    }
+}
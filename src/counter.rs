@@ -0,0 +1,124 @@
+use error::Result;
+use raw::{check_bit_count, LengthPrefix};
+use write::BitWrite;
+
+/// A `BitWrite` sink that only counts the bits written to it, for
+/// measuring a value without encoding it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitCounter {
+    bits: usize,
+    length_prefix: LengthPrefix,
+    max_len: usize,   // The longest length accepted.
+}
+
+impl BitCounter {
+
+    pub fn new() -> BitCounter {
+        BitCounter {
+            bits: 0,
+            length_prefix: LengthPrefix::U32,
+            max_len: usize::MAX,
+        }
+    }
+
+    /// Creates a new BitCounter that writes lengths like `sink`, so that
+    /// it counts what a write to `sink` would take.
+    pub fn for_sink<W: BitWrite + ?Sized>(sink: &W) -> BitCounter {
+        BitCounter {
+            bits: 0,
+            length_prefix: sink.length_prefix(),
+            max_len: sink.max_len(),
+        }
+    }
+
+    /// Changes how lengths are counted, as `BitBuf::set_length_prefix`.
+    pub fn set_length_prefix(&mut self, prefix: LengthPrefix) -> Result<()> {
+        prefix.check()?;
+        self.length_prefix = prefix;
+        Ok(())
+    }
+
+    /// Changes the longest length accepted, as `BitBuf::set_max_len`.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// The number of bits written so far.
+    pub fn bit_len(&self) -> usize {
+        self.bits
+    }
+
+    /// The number of bytes needed to hold the bits written so far.
+    pub fn byte_len(&self) -> usize {
+        self.bits.div_ceil(8)
+    }
+}
+
+impl Default for BitCounter {
+    fn default() -> BitCounter {
+        BitCounter::new()
+    }
+}
+
+impl BitWrite for BitCounter {
+    fn write_bits(&mut self, _value: u64, bits: u8) -> Result<()> {
+        check_bit_count(bits, 64)?;
+        self.bits += bits as usize;
+        Ok(())
+    }
+
+    fn reserve_bits(&mut self, _bits: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn bit_pos(&self) -> usize {
+        self.bits
+    }
+
+    fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
+    }
+
+    fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    fn write_u8_slice(&mut self, value: &[u8]) -> Result<()> {
+        self.bits += value.len() * 8;
+        Ok(())
+    }
+}
+
+#[test]
+fn counter_matches_bitbuf() {
+    use BitBuf;
+
+    let mut counter = BitCounter::new();
+    let mut buf = BitBuf::new();
+    for sink in [&mut counter as &mut dyn BitWrite, &mut buf] {
+        sink.write_bool(true).unwrap();
+        sink.write_i32_part(-54397, 22).unwrap();
+        sink.write_string("Counted, not copied.").unwrap();
+        sink.write_var_u64(24839402390).unwrap();
+        sink.write_f32_resolution(12.5, 0.0, 100.0, 0.01).unwrap();
+        sink.write_ranged_i32(-3, -10, 10).unwrap();
+    }
+    assert!(counter.bit_len() == buf.bit_pos());
+    assert!(counter.byte_len() == buf.finish().len());
+}
+
+#[test]
+fn bit_len_for_follows_sink() {
+    use {BitBuf, LengthPrefix, WriteToBitBuf};
+
+    let value = (String::from("hi"), vec![1u16, 2, 3]);
+    let mut buf = BitBuf::new();
+    buf.set_length_prefix(LengthPrefix::U8).unwrap();
+    value.write_to_bitbuf(&mut buf).unwrap();
+    assert!(value.bit_len_for(&buf).unwrap() == buf.bit_pos());
+    assert!(buf.bit_pos() == (8 + 2 * 8) + (8 + 3 * 16));
+    assert!(value.bit_len().unwrap() == buf.bit_pos() + 2 * 24);
+
+    buf.set_max_len(2);
+    assert!(value.bit_len_for(&buf).is_err());
+}
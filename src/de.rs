@@ -0,0 +1,273 @@
+use std::fmt::Display;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+
+use config::Config;
+use error::{BitBufError, Result};
+use impls::{capacity_hint, read_seq_len};
+use read::BitRead;
+
+/// Deserializes a `T` from `buf` at its cursor using the default
+/// configuration.
+pub fn from_bitbuf<T: DeserializeOwned, R: BitRead>(buf: &mut R) -> Result<T> {
+    from_bitbuf_with(buf, Config::default())
+}
+
+/// Deserializes a `T` from `buf` at its cursor using `config`.
+pub fn from_bitbuf_with<T: DeserializeOwned, R: BitRead>(buf: &mut R, config: Config) -> Result<T> {
+    T::deserialize(&mut Deserializer::new(buf, config))
+}
+
+impl de::Error for BitBufError {
+    fn custom<T: Display>(msg: T) -> BitBufError {
+        BitBufError::Custom(msg.to_string())
+    }
+}
+
+/// A serde Deserializer that reads the format written by `Serializer`.
+/// The format is not self-describing, so `deserialize_any` and
+/// `deserialize_ignored_any` are not supported.
+pub struct Deserializer<'a, R: 'a> {
+    buf: &'a mut R,
+    config: Config,
+}
+
+impl<'a, R: BitRead> Deserializer<'a, R> {
+    pub fn new(buf: &'a mut R, config: Config) -> Deserializer<'a, R> {
+        Deserializer { buf, config }
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        self.config.lengths(self.buf)?.read_bytes()
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        self.config.lengths(self.buf)?.read_string()
+    }
+
+    fn read_seq_len(&mut self) -> Result<usize> {
+        read_seq_len(&mut self.config.lengths(self.buf)?)
+    }
+}
+
+fn unsupported<T>(what: &str) -> Result<T> {
+    Err(BitBufError::Custom(format!("{} is not supported by the bitbuf format", what)))
+}
+
+impl<'de, 'a, 'b, R: BitRead> de::Deserializer<'de> for &'b mut Deserializer<'a, R> {
+    type Error = BitBufError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        unsupported("deserialize_any")
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_bool(self.buf.read_bool()?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(self.config.read_sint(self.buf, 8)? as i8)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i16(self.config.read_sint(self.buf, 16)? as i16)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(self.config.read_sint(self.buf, 32)? as i32)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(self.config.read_sint(self.buf, 64)?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u8(self.config.read_uint(self.buf, 8)? as u8)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u16(self.config.read_uint(self.buf, 16)? as u16)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(self.config.read_uint(self.buf, 32)? as u32)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(self.config.read_uint(self.buf, 64)?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(self.buf.read_f32()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(self.buf.read_f64()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match ::std::char::from_u32(self.buf.read_u32_part(21)?) {
+            Some(c) => visitor.visit_char(c),
+            None => Err(BitBufError::ValueOutOfRange),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.read_string()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.read_string()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.read_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.read_bytes()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.buf.read_bool()? {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.read_seq_len()?;
+        visitor.visit_seq(Access { de: self, len })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Access { de: self, len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Access { de: self, len })
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.read_seq_len()?;
+        visitor.visit_map(Access { de: self, len })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(Access { de: self, len: fields.len() })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        unsupported("deserialize_identifier")
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        unsupported("deserialize_ignored_any")
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Element access for sequences, tuples, structs and maps.
+struct Access<'c, 'a: 'c, R: 'a> {
+    de: &'c mut Deserializer<'a, R>,
+    len: usize,
+}
+
+impl<'de, 'c, 'a, R: BitRead> de::SeqAccess<'de> for Access<'c, 'a, R> {
+    type Error = BitBufError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    /// The element type is not known here, so only the remaining bits
+    /// and the preallocation limit bound the hint.
+    fn size_hint(&self) -> Option<usize> {
+        Some(capacity_hint::<u8, R>(self.de.buf, self.len))
+    }
+}
+
+impl<'de, 'c, 'a, R: BitRead> de::MapAccess<'de> for Access<'c, 'a, R> {
+    type Error = BitBufError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(capacity_hint::<u8, R>(self.de.buf, self.len))
+    }
+}
+
+impl<'de, 'a, 'b, R: BitRead> de::EnumAccess<'de> for &'b mut Deserializer<'a, R> {
+    type Error = BitBufError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index = self.config.read_uint(self.buf, 32)? as u32;
+        let value = seed.deserialize(IntoDeserializer::<BitBufError>::into_deserializer(index))?;
+        Ok((value, self))
+    }
+}
+
+impl<'de, 'a, 'b, R: BitRead> de::VariantAccess<'de> for &'b mut Deserializer<'a, R> {
+    type Error = BitBufError;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
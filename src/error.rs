@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::Utf8Error;
+
+/// The error type for fallible BitBuf reads and writes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BitBufError {
+    /// The operation needed more bits than remain in the buffer.
+    OutOfBounds { needed: usize, remaining: usize },
+    /// A bit count the operation does not support.
+    InvalidBitCount { bits: u8, max: u8 },
+    /// A length larger than the data left in the buffer, than the
+    /// maximum length allowed, or than the length prefix can hold.
+    InvalidLength(usize),
+    /// String data that is not valid UTF-8.
+    InvalidUtf8(Utf8Error),
+    /// A value outside the range its encoding allows, such as an
+    /// unknown enum variant.
+    ValueOutOfRange,
+    /// A read that needs byte aligned data found the cursor mid-byte.
+    NotByteAligned,
+    /// Padding bits that should have been zero were not.
+    InvalidPadding,
+    /// An error reported through serde.
+    Custom(String),
+}
+
+/// Shorthand for results of BitBuf operations.
+pub type Result<T> = ::std::result::Result<T, BitBufError>;
+
+impl fmt::Display for BitBufError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BitBufError::OutOfBounds { needed, remaining } => {
+                write!(f, "needed {} bits but only {} remain", needed, remaining)
+            },
+            BitBufError::InvalidBitCount { bits, max } => {
+                write!(f, "bit count {} is not supported here (max {})", bits, max)
+            },
+            BitBufError::InvalidLength(len) => {
+                write!(f, "length {} is too long for the remaining data, the maximum length or the length prefix", len)
+            },
+            BitBufError::InvalidUtf8(ref err) => {
+                write!(f, "invalid UTF-8: {}", err)
+            },
+            BitBufError::ValueOutOfRange => {
+                write!(f, "value is outside the range allowed by its encoding")
+            },
+            BitBufError::NotByteAligned => {
+                write!(f, "data does not start on a byte boundary")
+            },
+            BitBufError::InvalidPadding => {
+                write!(f, "padding bits are not zero")
+            },
+            BitBufError::Custom(ref msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl Error for BitBufError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BitBufError::InvalidUtf8(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<BitBufError> for io::Error {
+    fn from(err: BitBufError) -> io::Error {
+        let kind = match err {
+            BitBufError::OutOfBounds { .. } => io::ErrorKind::UnexpectedEof,
+            BitBufError::InvalidBitCount { .. } | BitBufError::NotByteAligned => {
+                io::ErrorKind::InvalidInput
+            },
+            BitBufError::Custom(_) => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,489 @@
+//! Splitting messages larger than a packet into fragments, and putting
+//! them back together. Each fragment starts with a 35-bit header: a
+//! 16-bit message id, an 8-bit fragment index, the 8-bit index of the
+//! last fragment, and 3 bits giving how much of the message's final byte
+//! is used. The rest of the fragment is message bytes.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use error::{BitBufError, Result};
+use raw::BitOrder;
+use read::BitRead;
+use reader::BitReader;
+use write::BitWrite;
+use BitBuf;
+
+const ID_BITS: u8 = 16;
+const INDEX_BITS: u8 = 8;
+const TAIL_BITS: u8 = 3;
+const HEADER_BITS: usize = (ID_BITS + 2 * INDEX_BITS + TAIL_BITS) as usize;
+
+/// The most fragments one message can be split into.
+pub const MAX_FRAGMENTS: usize = 1 << INDEX_BITS;
+
+/// How many of the most recently completed message ids a `Reassembler`
+/// remembers, to drop late duplicates of their fragments.
+const COMPLETED_IDS: usize = 256;
+
+/// Splits finished messages into fragments of at most `mtu` bytes,
+/// numbering each message with the next id.
+#[derive(Clone, Debug)]
+pub struct Fragmenter {
+    mtu: usize,
+    next_id: u16,
+}
+
+impl Fragmenter {
+
+    /// Creates a new Fragmenter for packets of at most `mtu` bytes.
+    pub fn new(mtu: usize) -> Fragmenter {
+        Fragmenter { mtu, next_id: 0 }
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// The id the next message will be given. Ids wrap after 65535.
+    pub fn next_id(&self) -> u16 {
+        self.next_id
+    }
+
+    /// Splits the bits written to `message` into fragments, each ready
+    /// to `finish` and send. An MTU too small to hold a header and one
+    /// byte, or a message needing more than `MAX_FRAGMENTS`, is rejected
+    /// with `InvalidLength`.
+    pub fn fragment(&mut self, message: &BitBuf) -> Result<Vec<BitBuf>> {
+        let payload = payload_bytes(self.mtu);
+        if payload == 0 {
+            return Err(BitBufError::InvalidLength(self.mtu));
+        }
+        let bytes = &message.buf[..message.written.div_ceil(8)];
+        let count = bytes.len().div_ceil(payload).max(1);
+        if count > MAX_FRAGMENTS {
+            return Err(BitBufError::InvalidLength(bytes.len()));
+        }
+        let id = self.next_id;
+        self.next_id = id.wrapping_add(1);
+
+        let mut fragments = Vec::with_capacity(count);
+        for index in 0..count {
+            let start = (index * payload).min(bytes.len());
+            let chunk = &bytes[start..(start + payload).min(bytes.len())];
+            let mut fragment = BitBuf::with_capacity(self.mtu);
+            fragment.write_u16(id)?;
+            fragment.write_u8(index as u8)?;
+            fragment.write_u8((count - 1) as u8)?;
+            fragment.write_u8_part((message.written & 0x7) as u8, TAIL_BITS)?;
+            fragment.write_u8_slice(chunk)?;
+            fragments.push(fragment);
+        }
+        Ok(fragments)
+    }
+}
+
+/// A message with some fragments still to arrive.
+#[derive(Debug)]
+struct Pending {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    tail: u8,
+    full_len: Option<usize>,  // The payload length of every fragment but the last.
+    last_len: Option<usize>,  // The payload length of the last fragment.
+    bytes: usize,             // Payload bytes held so far.
+    started: Instant,         // When the first fragment arrived.
+}
+
+/// Collects fragments made by a `Fragmenter`, in any order, and returns
+/// each message once all of its fragments have arrived. Duplicates are
+/// ignored, even after their message completed if it is among the last
+/// 256 to complete, and messages still incomplete after the timeout are
+/// dropped.
+///
+/// Fragments usually come from untrusted peers, so the memory held is
+/// bounded: no fragment may carry more than fits the MTU, and when a new
+/// message or fragment would exceed `max_pending` or `max_buffered`, the
+/// oldest incomplete messages are dropped to make room.
+#[derive(Debug)]
+pub struct Reassembler {
+    payload: usize,       // The most payload bytes one fragment can carry.
+    timeout: Duration,
+    max_pending: usize,
+    max_buffered: usize,
+    buffered: usize,      // Payload bytes held across all pending messages.
+    order: BitOrder,      // The order reassembled messages are read in.
+    pending: HashMap<u16, Pending>,
+    completed: VecDeque<u16>,  // The most recently completed ids, newest last.
+}
+
+impl Reassembler {
+
+    /// Creates a new Reassembler for fragments of at most `mtu` bytes,
+    /// that gives up on a message `timeout` after its first fragment
+    /// arrived. It holds at most 64 incomplete messages and 1 MiB of
+    /// payload until changed.
+    pub fn new(mtu: usize, timeout: Duration) -> Reassembler {
+        Reassembler {
+            payload: payload_bytes(mtu),
+            timeout,
+            max_pending: 64,
+            max_buffered: 1 << 20,
+            buffered: 0,
+            order: BitOrder::LsbFirst,
+            pending: HashMap::new(),
+            completed: VecDeque::with_capacity(COMPLETED_IDS),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The number of incomplete messages being held.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The number of payload bytes held by incomplete messages.
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    /// The most incomplete messages held at once. Defaults to 64.
+    pub fn max_pending(&self) -> usize {
+        self.max_pending
+    }
+
+    /// Changes the most incomplete messages held at once, which is at
+    /// least one. Takes effect as fragments arrive.
+    pub fn set_max_pending(&mut self, max_pending: usize) {
+        self.max_pending = max_pending.max(1);
+    }
+
+    /// The most payload bytes held across incomplete messages. Defaults
+    /// to 1 MiB.
+    pub fn max_buffered(&self) -> usize {
+        self.max_buffered
+    }
+
+    /// Changes the most payload bytes held across incomplete messages.
+    /// Takes effect as fragments arrive.
+    pub fn set_max_buffered(&mut self, max_buffered: usize) {
+        self.max_buffered = max_buffered;
+    }
+
+    /// The bit order reassembled messages are given. Defaults to
+    /// `BitOrder::LsbFirst`.
+    pub fn bit_order(&self) -> BitOrder {
+        self.order
+    }
+
+    /// Changes the bit order reassembled messages are given. Fragments
+    /// do not carry it, so it must match the order the sender wrote its
+    /// messages in.
+    pub fn set_bit_order(&mut self, order: BitOrder) {
+        self.order = order;
+    }
+
+    /// Accepts one fragment received at `now`, first dropping timed out
+    /// messages. Returns the whole message, rewound and ready to read in
+    /// `bit_order()`, when this was its last missing fragment. Fragments
+    /// of a recently completed message are ignored.
+    ///
+    /// A payload larger than the MTU allows is rejected with
+    /// `InvalidLength`, as is a message that alone needs more than
+    /// `max_buffered` bytes, which is then dropped. A header or payload
+    /// length that does not match earlier fragments of the same id is
+    /// rejected with `ValueOutOfRange`.
+    pub fn accept(&mut self, fragment: &[u8], now: Instant) -> Result<Option<BitBuf>> {
+        self.expire(now);
+        let mut reader = BitReader::new(fragment);
+        let id = reader.read_u16()?;
+        let index = reader.read_u8()? as usize;
+        let last = reader.read_u8()? as usize;
+        let tail = reader.read_u8_part(TAIL_BITS)?;
+        if index > last {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        let len = reader.remaining_bits() / 8;
+        if len > self.payload {
+            return Err(BitBufError::InvalidLength(len));
+        }
+        if self.completed.contains(&id) {
+            return Ok(None);
+        }
+
+        if !self.pending.contains_key(&id) {
+            while self.pending.len() >= self.max_pending && self.evict_oldest(id) {}
+            self.pending.insert(id, Pending {
+                fragments: vec![None; last + 1],
+                received: 0,
+                tail,
+                full_len: None,
+                last_len: None,
+                bytes: 0,
+                started: now,
+            });
+        }
+        {
+            let pending = self.pending.get_mut(&id).unwrap();
+            if pending.fragments.len() != last + 1 || pending.tail != tail {
+                return Err(BitBufError::ValueOutOfRange);
+            }
+            if pending.fragments[index].is_some() {
+                return Ok(None);
+            }
+            // Every fragment but the last carries the same, largest payload.
+            if index < last {
+                let mismatch = pending.full_len.is_some_and(|full| full != len)
+                    || pending.last_len.is_some_and(|last_len| last_len > len);
+                if mismatch {
+                    return Err(BitBufError::ValueOutOfRange);
+                }
+                pending.full_len = Some(len);
+            } else {
+                if pending.full_len.is_some_and(|full| len > full) {
+                    return Err(BitBufError::ValueOutOfRange);
+                }
+                pending.last_len = Some(len);
+            }
+        }
+
+        while self.buffered + len > self.max_buffered {
+            if !self.evict_oldest(id) {
+                let bytes = self.pending.remove(&id).map_or(0, |pending| pending.bytes);
+                self.buffered -= bytes;
+                return Err(BitBufError::InvalidLength(bytes + len));
+            }
+        }
+        let pending = self.pending.get_mut(&id).unwrap();
+        pending.fragments[index] = Some(reader.read_vec_u8(len)?);
+        pending.received += 1;
+        pending.bytes += len;
+        self.buffered += len;
+        if pending.received < pending.fragments.len() {
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(&id).unwrap();
+        self.buffered -= pending.bytes;
+        if self.completed.len() == COMPLETED_IDS {
+            self.completed.pop_front();
+        }
+        self.completed.push_back(id);
+        let mut message = BitBuf::with_capacity(pending.bytes);
+        message.set_bit_order(self.order);
+        for bytes in pending.fragments.iter().flatten() {
+            message.write_u8_slice(bytes)?;
+        }
+        if pending.tail != 0 && message.written >= 8 {
+            message.written -= 8 - pending.tail as usize;
+        }
+        message.rewind()?;
+        Ok(Some(message))
+    }
+
+    /// Drops messages whose first fragment arrived `timeout` or more
+    /// before `now`, returning how many were dropped.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.pending.len();
+        let timeout = self.timeout;
+        let buffered = &mut self.buffered;
+        self.pending.retain(|_, pending| {
+            let keep = now.saturating_duration_since(pending.started) < timeout;
+            if !keep {
+                *buffered -= pending.bytes;
+            }
+            keep
+        });
+        before - self.pending.len()
+    }
+
+    /// Drops the incomplete message that started first, other than
+    /// `keep`. Returns false if there was none.
+    fn evict_oldest(&mut self, keep: u16) -> bool {
+        let oldest = self.pending
+            .iter()
+            .filter(|&(&id, _)| id != keep)
+            .min_by_key(|&(_, pending)| pending.started)
+            .map(|(&id, _)| id);
+        match oldest.and_then(|id| self.pending.remove(&id)) {
+            Some(pending) => {
+                self.buffered -= pending.bytes;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// The most payload bytes a fragment of `mtu` bytes can carry.
+fn payload_bytes(mtu: usize) -> usize {
+    mtu.saturating_mul(8).saturating_sub(HEADER_BITS) / 8
+}
+
+#[test]
+fn fragment_reassemble_equal() {
+    let mut message = BitBuf::new();
+    for i in 0..1000u32 {
+        message.write_u32_part(i, 13).unwrap();
+    }
+    message.write_bool(true).unwrap();
+    let bits = message.bit_pos();
+
+    let mut fragmenter = Fragmenter::new(300);
+    let fragments: Vec<Vec<u8>> = fragmenter
+        .fragment(&message)
+        .unwrap()
+        .into_iter()
+        .map(BitBuf::finish)
+        .collect();
+    assert!(fragments.len() == 6 && fragments.iter().all(|f| f.len() <= 300));
+    assert!(fragmenter.next_id() == 1);
+
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(300, Duration::from_secs(1));
+    for &i in &[3, 0, 5, 0, 1, 4] {
+        assert!(reassembler.accept(&fragments[i], now).unwrap().is_none());
+    }
+    assert!(reassembler.pending() == 1);
+    let mut result = reassembler.accept(&fragments[2], now).unwrap().unwrap();
+    assert!(reassembler.pending() == 0);
+    for i in 0..1000u32 {
+        assert!(result.read_u32_part(13).unwrap() == i);
+    }
+    assert!(result.read_bool().unwrap());
+    assert!(result.written == bits);
+    assert!(result.finish() == message.finish());
+}
+
+#[test]
+fn reassembler_times_out() {
+    let mut message = BitBuf::new();
+    message.write_u8_slice(&[7; 100]).unwrap();
+    let fragments = Fragmenter::new(40).fragment(&message).unwrap();
+    let first = fragments[0].clone().finish();
+
+    let start = Instant::now();
+    let mut reassembler = Reassembler::new(40, Duration::from_millis(100));
+    assert!(reassembler.accept(&first, start).unwrap().is_none());
+    assert!(reassembler.expire(start + Duration::from_millis(99)) == 0);
+    assert!(reassembler.expire(start + Duration::from_millis(100)) == 1);
+    assert!(reassembler.pending() == 0);
+}
+
+#[test]
+fn fragment_errors() {
+    let mut message = BitBuf::new();
+    message.write_u8_slice(&[0; 300]).unwrap();
+    assert!(Fragmenter::new(5).fragment(&message).err() == Some(BitBufError::InvalidLength(5)));
+    assert!(Fragmenter::new(6).fragment(&message).err() == Some(BitBufError::InvalidLength(300)));
+
+    let fragments = Fragmenter::new(200).fragment(&message).unwrap();
+    let mut reassembler = Reassembler::new(200, Duration::from_secs(1));
+    let now = Instant::now();
+    reassembler.accept(&fragments[0].clone().finish(), now).unwrap();
+    let mut bad = fragments[1].clone().finish();
+    bad[3] = 2;
+    assert!(reassembler.accept(&bad, now).err() == Some(BitBufError::ValueOutOfRange));
+    assert!(reassembler.accept(&[0, 0], now).is_err());
+}
+
+#[test]
+fn reassembler_bounds_memory() {
+    let mut message = BitBuf::new();
+    message.write_u8_slice(&[1; 100]).unwrap();
+    let mut fragmenter = Fragmenter::new(40);
+    let start = Instant::now();
+    let mut reassembler = Reassembler::new(40, Duration::from_secs(10));
+    reassembler.set_max_pending(2);
+    reassembler.set_max_buffered(100);
+
+    // Three messages' first fragments: the oldest is dropped for the third.
+    let mut messages = Vec::new();
+    for i in 0..3 {
+        let fragments = fragmenter.fragment(&message).unwrap();
+        let first = fragments[0].clone().finish();
+        let at = start + Duration::from_millis(i);
+        assert!(reassembler.accept(&first, at).unwrap().is_none());
+        messages.push(fragments);
+    }
+    assert!(reassembler.pending() == 2 && reassembler.buffered() == 70);
+
+    // Room for the second fragment of the newest is made by dropping the
+    // older one; the newest still completes.
+    let now = start + Duration::from_millis(3);
+    let second = messages[2][1].clone().finish();
+    assert!(reassembler.accept(&second, now).unwrap().is_none());
+    assert!(reassembler.pending() == 1 && reassembler.buffered() == 70);
+    let last = messages[2][2].clone().finish();
+    assert!(reassembler.accept(&last, now).unwrap().unwrap().finish() == [1; 100]);
+    assert!(reassembler.buffered() == 0);
+
+    // A message bigger than the whole budget is dropped.
+    reassembler.set_max_buffered(50);
+    let fragments = fragmenter.fragment(&message).unwrap();
+    reassembler.accept(&fragments[0].clone().finish(), now).unwrap();
+    let second = fragments[1].clone().finish();
+    assert!(reassembler.accept(&second, now).err() == Some(BitBufError::InvalidLength(70)));
+    assert!(reassembler.pending() == 0 && reassembler.buffered() == 0);
+}
+
+#[test]
+fn reassembler_payload_errors() {
+    let mut message = BitBuf::new();
+    message.write_u8_slice(&[2; 100]).unwrap();
+    let fragments = Fragmenter::new(60).fragment(&message).unwrap();
+    let now = Instant::now();
+
+    // Larger than the receiver's MTU allows.
+    let mut reassembler = Reassembler::new(40, Duration::from_secs(1));
+    let first = fragments[0].clone().finish();
+    assert!(reassembler.accept(&first, now).err() == Some(BitBufError::InvalidLength(55)));
+    assert!(reassembler.pending() == 0);
+
+    // Longer than the other full fragments of the same message.
+    let mut reassembler = Reassembler::new(60, Duration::from_secs(1));
+    let short = Fragmenter::new(40).fragment(&message).unwrap();
+    reassembler.accept(&short[0].clone().finish(), now).unwrap();
+    let mut long = fragments[1].clone().finish();
+    long[3] = 2;
+    assert!(reassembler.accept(&long, now).err() == Some(BitBufError::ValueOutOfRange));
+}
+
+#[test]
+fn reassembler_drops_completed_duplicates() {
+    let mut message = BitBuf::new();
+    message.write_u8(42).unwrap();
+    let mut fragmenter = Fragmenter::new(40);
+    let single = fragmenter.fragment(&message).unwrap()[0].clone().finish();
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(40, Duration::from_secs(1));
+    assert!(reassembler.accept(&single, now).unwrap().unwrap().finish() == [42]);
+    assert!(reassembler.accept(&single, now).unwrap().is_none());
+
+    // A late copy of a fragment of a completed message opens nothing.
+    message.write_u8_slice(&[3; 99]).unwrap();
+    let fragments = fragmenter.fragment(&message).unwrap();
+    for fragment in &fragments {
+        reassembler.accept(&fragment.clone().finish(), now).unwrap();
+    }
+    let late = fragments[1].clone().finish();
+    assert!(reassembler.accept(&late, now).unwrap().is_none());
+    assert!(reassembler.pending() == 0 && reassembler.buffered() == 0);
+}
+
+#[test]
+fn reassembler_keeps_bit_order() {
+    let mut message = BitBuf::new();
+    message.set_bit_order(BitOrder::MsbFirst);
+    message.write_u16_part(0x1ABC, 13).unwrap();
+    let fragments = Fragmenter::new(40).fragment(&message).unwrap();
+    let mut reassembler = Reassembler::new(40, Duration::from_secs(1));
+    reassembler.set_bit_order(BitOrder::MsbFirst);
+    let single = fragments[0].clone().finish();
+    let mut result = reassembler.accept(&single, Instant::now()).unwrap().unwrap();
+    assert!(result.bit_order() == BitOrder::MsbFirst);
+    assert!(result.read_u16_part(13).unwrap() == 0x1ABC);
+}
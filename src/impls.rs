@@ -0,0 +1,479 @@
+//! `WriteToBitBuf` and `FromBitBuf` for std types. Strings and
+//! collections are written as a length prefix in the sink's
+//! `length_prefix()` followed by their elements in iteration order;
+//! `Option` is a presence bit and the value.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryInto;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+
+use error::{BitBufError, Result};
+use read::BitRead;
+use write::BitWrite;
+use {FromBitBuf, WriteToBitBuf};
+
+macro_rules! impl_primitive {
+    ($($ty:ty => $write:ident, $read:ident;)*) => {$(
+        impl WriteToBitBuf for $ty {
+            fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+                buf.$write(*self)
+            }
+        }
+
+        impl FromBitBuf for $ty {
+            fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<$ty> {
+                buf.$read()
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_delta_primitive {
+    ($($ty:ty => $write:ident, $read:ident, $write_delta:ident, $read_delta:ident;)*) => {$(
+        impl WriteToBitBuf for $ty {
+            fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+                buf.$write(*self)
+            }
+
+            fn write_delta<W: BitWrite>(&self, baseline: &$ty, buf: &mut W) -> Result<()> {
+                buf.$write_delta(*self, *baseline)
+            }
+        }
+
+        impl FromBitBuf for $ty {
+            fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<$ty> {
+                buf.$read()
+            }
+
+            fn read_delta<R: BitRead>(baseline: &$ty, buf: &mut R) -> Result<$ty> {
+                buf.$read_delta(*baseline)
+            }
+        }
+    )*};
+}
+
+impl_primitive! {
+    bool => write_bool, read_bool;
+}
+
+impl_delta_primitive! {
+    u8 => write_u8, read_u8, write_delta_u8, read_delta_u8;
+    u16 => write_u16, read_u16, write_delta_u16, read_delta_u16;
+    u32 => write_u32, read_u32, write_delta_u32, read_delta_u32;
+    u64 => write_u64, read_u64, write_delta_u64, read_delta_u64;
+    i8 => write_i8, read_i8, write_delta_i8, read_delta_i8;
+    i16 => write_i16, read_i16, write_delta_i16, read_delta_i16;
+    i32 => write_i32, read_i32, write_delta_i32, read_delta_i32;
+    i64 => write_i64, read_i64, write_delta_i64, read_delta_i64;
+    f32 => write_f32, read_f32, write_delta_f32, read_delta_f32;
+    f64 => write_f64, read_f64, write_delta_f64, read_delta_f64;
+}
+
+/// Written low half first, as two u64s.
+impl WriteToBitBuf for u128 {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        buf.reserve_bits(128)?;
+        buf.write_u64(*self as u64)?;
+        buf.write_u64((*self >> 64) as u64)
+    }
+}
+
+impl FromBitBuf for u128 {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<u128> {
+        if !buf.can_read_bits(128) {
+            return Err(BitBufError::OutOfBounds { needed: 128, remaining: buf.remaining_bits() });
+        }
+        let low = buf.read_u64()? as u128;
+        Ok(low | (buf.read_u64()? as u128) << 64)
+    }
+}
+
+impl WriteToBitBuf for i128 {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        (*self as u128).write_to_bitbuf(buf)
+    }
+}
+
+impl FromBitBuf for i128 {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<i128> {
+        Ok(u128::from_bitbuf(buf)? as i128)
+    }
+}
+
+/// Written as 64 bits so the encoding does not depend on the platform.
+impl WriteToBitBuf for usize {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        buf.write_u64(*self as u64)
+    }
+}
+
+impl FromBitBuf for usize {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<usize> {
+        let value = buf.read_u64()?;
+        if value > usize::MAX as u64 {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        Ok(value as usize)
+    }
+}
+
+impl WriteToBitBuf for isize {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        buf.write_i64(*self as i64)
+    }
+}
+
+impl FromBitBuf for isize {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<isize> {
+        let value = buf.read_i64()?;
+        if value < isize::MIN as i64 || value > isize::MAX as i64 {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        Ok(value as isize)
+    }
+}
+
+/// Written in 21 bits, enough for any Unicode scalar value.
+impl WriteToBitBuf for char {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        buf.write_u32_part(*self as u32, 21)
+    }
+}
+
+impl FromBitBuf for char {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<char> {
+        let start = buf.bit_pos();
+        match ::std::char::from_u32(buf.read_u32_part(21)?) {
+            Some(value) => Ok(value),
+            None => {
+                buf.set_bit_pos(start)?;
+                Err(BitBufError::ValueOutOfRange)
+            },
+        }
+    }
+}
+
+impl WriteToBitBuf for str {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        buf.write_string(self)
+    }
+}
+
+/// Deltas are a 0 bit if the string is unchanged, otherwise a 1 bit
+/// and the whole string.
+impl WriteToBitBuf for String {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        buf.write_string(self)
+    }
+
+    fn write_delta<W: BitWrite>(&self, baseline: &String, buf: &mut W) -> Result<()> {
+        if self == baseline {
+            return buf.write_bool(false);
+        }
+        let len_bits = buf.len_bits(self.len())?;
+        buf.reserve_bits(self.len().saturating_mul(8).saturating_add(len_bits + 1))?;
+        buf.write_bool(true)?;
+        buf.write_string(self)
+    }
+}
+
+impl FromBitBuf for String {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<String> {
+        buf.read_string()
+    }
+
+    fn read_delta<R: BitRead>(baseline: &String, buf: &mut R) -> Result<String> {
+        if buf.read_bool()? {
+            buf.read_string()
+        } else {
+            Ok(baseline.clone())
+        }
+    }
+}
+
+impl<T: ?Sized + WriteToBitBuf> WriteToBitBuf for &T {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        (**self).write_to_bitbuf(buf)
+    }
+
+    fn write_delta<W: BitWrite>(&self, baseline: &Self, buf: &mut W) -> Result<()> {
+        (**self).write_delta(baseline, buf)
+    }
+}
+
+impl<T: ?Sized + WriteToBitBuf> WriteToBitBuf for Box<T> {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        (**self).write_to_bitbuf(buf)
+    }
+
+    fn write_delta<W: BitWrite>(&self, baseline: &Box<T>, buf: &mut W) -> Result<()> {
+        (**self).write_delta(baseline, buf)
+    }
+}
+
+impl<T: FromBitBuf> FromBitBuf for Box<T> {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<Box<T>> {
+        Ok(Box::new(T::from_bitbuf(buf)?))
+    }
+
+    fn read_delta<R: BitRead>(baseline: &Box<T>, buf: &mut R) -> Result<Box<T>> {
+        Ok(Box::new(T::read_delta(baseline, buf)?))
+    }
+}
+
+impl<T: WriteToBitBuf> WriteToBitBuf for Option<T> {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        match *self {
+            Some(ref value) => {
+                buf.write_bool(true)?;
+                value.write_to_bitbuf(buf)
+            },
+            None => buf.write_bool(false),
+        }
+    }
+}
+
+impl<T: FromBitBuf> FromBitBuf for Option<T> {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<Option<T>> {
+        if buf.read_bool()? {
+            Ok(Some(T::from_bitbuf(buf)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Arrays have a fixed length, so no prefix is written.
+impl<T: WriteToBitBuf, const N: usize> WriteToBitBuf for [T; N] {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        for value in self {
+            value.write_to_bitbuf(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: FromBitBuf, const N: usize> FromBitBuf for [T; N] {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<[T; N]> {
+        let mut values = Vec::with_capacity(N);
+        for _ in 0..N {
+            values.push(T::from_bitbuf(buf)?);
+        }
+        values.try_into().map_err(|values: Vec<T>| {
+            BitBufError::Custom(format!("read {} of {} array elements", values.len(), N))
+        })
+    }
+}
+
+impl<T: WriteToBitBuf> WriteToBitBuf for [T] {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        write_seq(buf, self.len(), self.iter())
+    }
+}
+
+impl<T: WriteToBitBuf> WriteToBitBuf for Vec<T> {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        write_seq(buf, self.len(), self.iter())
+    }
+}
+
+impl<T: FromBitBuf> FromBitBuf for Vec<T> {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<Vec<T>> {
+        let len = read_seq_len(buf)?;
+        let mut values = Vec::with_capacity(capacity_hint::<T, R>(buf, len));
+        for _ in 0..len {
+            values.push(T::from_bitbuf(buf)?);
+        }
+        Ok(values)
+    }
+}
+
+impl<T: WriteToBitBuf> WriteToBitBuf for VecDeque<T> {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        write_seq(buf, self.len(), self.iter())
+    }
+}
+
+impl<T: FromBitBuf> FromBitBuf for VecDeque<T> {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<VecDeque<T>> {
+        Ok(Vec::from_bitbuf(buf)?.into())
+    }
+}
+
+impl<K: WriteToBitBuf, V: WriteToBitBuf, S> WriteToBitBuf for HashMap<K, V, S> {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        write_seq(buf, self.len(), self.iter())
+    }
+}
+
+impl<K, V, S> FromBitBuf for HashMap<K, V, S>
+    where K: FromBitBuf + Eq + Hash, V: FromBitBuf, S: BuildHasher + Default
+{
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<HashMap<K, V, S>> {
+        let len = read_seq_len(buf)?;
+        let capacity = capacity_hint::<(K, V), R>(buf, len);
+        let mut map = HashMap::with_capacity_and_hasher(capacity, S::default());
+        for _ in 0..len {
+            let key = K::from_bitbuf(buf)?;
+            map.insert(key, V::from_bitbuf(buf)?);
+        }
+        Ok(map)
+    }
+}
+
+impl<K: WriteToBitBuf, V: WriteToBitBuf> WriteToBitBuf for BTreeMap<K, V> {
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+        write_seq(buf, self.len(), self.iter())
+    }
+}
+
+impl<K: FromBitBuf + Ord, V: FromBitBuf> FromBitBuf for BTreeMap<K, V> {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<BTreeMap<K, V>> {
+        let len = read_seq_len(buf)?;
+        let mut map = BTreeMap::new();
+        for _ in 0..len {
+            let key = K::from_bitbuf(buf)?;
+            map.insert(key, V::from_bitbuf(buf)?);
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident)*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: WriteToBitBuf),*> WriteToBitBuf for ($($name,)*) {
+            #[allow(unused_variables)]
+            fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()> {
+                let ($(ref $name,)*) = *self;
+                $($name.write_to_bitbuf(buf)?;)*
+                Ok(())
+            }
+        }
+
+        impl<$($name: FromBitBuf),*> FromBitBuf for ($($name,)*) {
+            #[allow(unused_variables)]
+            fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<($($name,)*)> {
+                Ok(($($name::from_bitbuf(buf)?,)*))
+            }
+        }
+    };
+}
+
+impl_tuple!();
+impl_tuple!(A);
+impl_tuple!(A B);
+impl_tuple!(A B C);
+impl_tuple!(A B C D);
+impl_tuple!(A B C D E);
+impl_tuple!(A B C D E F);
+impl_tuple!(A B C D E F G);
+impl_tuple!(A B C D E F G H);
+impl_tuple!(A B C D E F G H I);
+impl_tuple!(A B C D E F G H I J);
+impl_tuple!(A B C D E F G H I J K);
+impl_tuple!(A B C D E F G H I J K L);
+
+fn write_seq<W, I, T>(buf: &mut W, len: usize, values: I) -> Result<()>
+    where W: BitWrite, I: Iterator<Item = T>, T: WriteToBitBuf
+{
+    buf.write_len(len)?;
+    for value in values {
+        value.write_to_bitbuf(buf)?;
+    }
+    Ok(())
+}
+
+/// The most bytes a collection preallocates before its elements are read.
+const MAX_PREALLOC_BYTES: usize = 1 << 20;
+
+/// How far a sequence length may exceed the bits remaining. Only
+/// elements that read no bits, such as `()`, can make up the difference.
+const MAX_ZERO_BIT_ELEMENTS: usize = 1 << 16;
+
+/// Reads a sequence length, rejecting one that could only be met by more
+/// than `MAX_ZERO_BIT_ELEMENTS` elements that read no bits, so a hostile
+/// prefix cannot make decoding loop for long.
+pub(crate) fn read_seq_len<R: BitRead>(buf: &mut R) -> Result<usize> {
+    let start = buf.bit_pos();
+    let len = buf.read_len()?;
+    if len > buf.remaining_bits().saturating_add(MAX_ZERO_BIT_ELEMENTS) {
+        buf.set_bit_pos(start)?;
+        return Err(BitBufError::InvalidLength(len));
+    }
+    Ok(len)
+}
+
+/// Limits preallocation to what the remaining data could hold and to
+/// `MAX_PREALLOC_BYTES` of `T`, so a hostile length prefix cannot reserve
+/// huge amounts of memory.
+pub(crate) fn capacity_hint<T, R: BitRead>(buf: &R, len: usize) -> usize {
+    let budget = MAX_PREALLOC_BYTES / mem::size_of::<T>().max(1);
+    len.min(buf.remaining_bits()).min(budget)
+}
+
+#[test]
+fn std_types_writeread_equal() {
+    use BitBuf;
+
+    let mut map = HashMap::new();
+    map.insert(String::from("health"), (100u8, Some(-2.5f32)));
+    map.insert(String::from("armor"), (35, None));
+    let mut tree = BTreeMap::new();
+    tree.insert(-4i64, vec!['a', 'ß']);
+    let queue: VecDeque<Box<u128>> = vec![Box::new(u128::MAX), Box::new(7)].into();
+    let value = (map, tree, queue, [true, false, true], (usize::MAX, -5isize, ()));
+
+    let mut buf = BitBuf::new();
+    value.write_to_bitbuf(&mut buf).unwrap();
+    "borrowed".write_to_bitbuf(&mut buf).unwrap();
+    assert!(value.bit_len().unwrap() + "borrowed".bit_len().unwrap() == buf.bit_pos());
+    buf.rewind().unwrap();
+    assert!(FromBitBuf::from_bitbuf(&mut buf) == Ok(value));
+    assert!(String::from_bitbuf(&mut buf).unwrap() == "borrowed");
+}
+
+#[test]
+fn collection_length_prefix_configurable() {
+    use {BitBuf, BitCounter, LengthPrefix};
+
+    let values = vec![1u8, 2, 3];
+    let mut buf = BitBuf::new();
+    buf.set_length_prefix(LengthPrefix::Fixed(5)).unwrap();
+    values.write_to_bitbuf(&mut buf).unwrap();
+    assert!(buf.bit_pos() == 5 + 3 * 8);
+    let mut counter = BitCounter::new();
+    counter.set_length_prefix(LengthPrefix::Fixed(5)).unwrap();
+    values.write_to_bitbuf(&mut counter).unwrap();
+    assert!(counter.bit_len() == buf.bit_pos());
+    buf.rewind().unwrap();
+    assert!(Vec::<u8>::from_bitbuf(&mut buf).unwrap() == values);
+
+    let too_long = vec![0u8; 32];
+    assert!(too_long.write_to_bitbuf(&mut buf) == Err(BitBufError::InvalidLength(32)));
+    assert!(buf.set_length_prefix(LengthPrefix::Fixed(0)).is_err());
+    assert!(buf.set_length_prefix(LengthPrefix::Fixed(65)).is_err());
+}
+
+#[test]
+fn hostile_length_errors() {
+    use {BitBuf, BitReader};
+
+    let bytes = [0xFF, 0xFF, 0xFF, 0x0F];
+    let mut reader = BitReader::new(&bytes);
+    assert!(Vec::<()>::from_bitbuf(&mut reader) == Err(BitBufError::InvalidLength(0x0FFFFFFF)));
+    assert!(reader.bit_pos() == 0);
+
+    let mut bytes = vec![0; 1400];
+    bytes[..4].copy_from_slice(&11000u32.to_le_bytes());
+    let reader = BitReader::new(&bytes);
+    assert!(capacity_hint::<[u64; 128], _>(&reader, 11000) == 1024);
+    assert!(Vec::<[u64; 128]>::from_bitbuf(&mut BitReader::new(&bytes)).is_err());
+
+    let units = vec![(); 1000];
+    let mut buf = BitBuf::new();
+    units.write_to_bitbuf(&mut buf).unwrap();
+    buf.rewind().unwrap();
+    assert!(Vec::<()>::from_bitbuf(&mut buf).unwrap() == units);
+}
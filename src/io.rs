@@ -0,0 +1,87 @@
+//! `std::io` support, so byte-oriented code can read and write a BitBuf
+//! around its bit-packed sections. The cursor may sit mid-byte, in which
+//! case every byte is shifted across the boundary.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use {BitBuf, BitRead, BitWrite};
+
+/// Reads whole bytes from the cursor. Fewer than 8 remaining bits count
+/// as the end of the stream.
+impl Read for BitBuf {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let len = out.len().min(self.remaining_bits() / 8);
+        self.read_into(&mut out[..len])?;
+        Ok(len)
+    }
+}
+
+/// Writes bytes at the cursor. A fixed size buffer accepts as many whole
+/// bytes as still fit; a growable one accepts everything.
+impl Write for BitBuf {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = if self.is_growable() {
+            data.len()
+        } else {
+            data.len().min(self.remaining_bits() / 8)
+        };
+        self.write_u8_slice(&data[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Seeks in bytes. Relative seeks keep the offset within the current
+/// byte, and the returned position is rounded down to a whole byte.
+/// `SeekFrom::End` is relative to the buffer's size, as in `seek_bits`.
+impl Seek for BitBuf {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let bits = match pos {
+            SeekFrom::Start(offset) => offset.checked_mul(8).map(SeekFrom::Start),
+            SeekFrom::End(offset) => offset.checked_mul(8).map(SeekFrom::End),
+            SeekFrom::Current(offset) => offset.checked_mul(8).map(SeekFrom::Current),
+        };
+        let bits = bits.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek offset overflows")
+        })?;
+        match self.seek_bits(bits) {
+            Ok(pos) => Ok((pos / 8) as u64),
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidInput, err)),
+        }
+    }
+}
+
+#[test]
+fn io_unaligned_writeread_equal() {
+    let mut buf = BitBuf::new();
+    buf.write_u8_part(5, 3).unwrap();
+    buf.write_all(b"bytes after a header").unwrap();
+    buf.rewind().unwrap();
+    assert!(buf.read_u8_part(3).unwrap() == 5);
+    let mut out = Vec::new();
+    io::copy(&mut buf, &mut out).unwrap();
+    assert!(out == b"bytes after a header");
+}
+
+#[test]
+fn io_fixed_write_stops_at_end() {
+    let mut buf = BitBuf::with_len(4);
+    buf.write_bool(true).unwrap();
+    assert!(buf.write(b"abcd").unwrap() == 3);
+    assert!(buf.write_all(b"e").unwrap_err().kind() == io::ErrorKind::WriteZero);
+}
+
+#[test]
+fn io_seek_moves_by_bytes() {
+    let mut buf = BitBuf::with_len(4);
+    buf.write_all(&[1, 2, 3, 4]).unwrap();
+    assert!(buf.seek(SeekFrom::Start(1)).unwrap() == 1);
+    assert!(buf.read_u8().unwrap() == 2);
+    assert!(buf.seek(SeekFrom::End(-1)).unwrap() == 3);
+    assert!(buf.read_u8().unwrap() == 4);
+    assert!(buf.seek(SeekFrom::Current(-5)).is_err());
+    assert!(buf.bit_pos() == 32);
+}
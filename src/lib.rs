@@ -1,49 +1,93 @@
-use std::mem::transmute;
+#[cfg(feature = "derive")]
+#[allow(unused_imports)]
+#[macro_use]
+extern crate bitbuf_derive;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+use std::ops::Range;
+
+mod checksum;
+#[cfg(feature = "serde")]
+mod config;
+mod counter;
+#[cfg(feature = "serde")]
+mod de;
+mod error;
+mod fragment;
+mod impls;
+mod io;
+mod queue;
+mod raw;
+mod read;
+mod reader;
+mod write;
+#[cfg(feature = "serde")]
+mod ser;
+
+pub use counter::BitCounter;
+pub use error::{BitBufError, Result};
+pub use fragment::{Fragmenter, Reassembler, MAX_FRAGMENTS};
+pub use queue::BitQueue;
+pub use raw::{BitOrder, LengthPrefix};
+pub use read::BitRead;
+pub use reader::BitReader;
+pub use write::BitWrite;
+
+#[cfg(feature = "derive")]
+pub use bitbuf_derive::{FromBitBuf, WriteToBitBuf};
+#[cfg(feature = "serde")]
+pub use config::{Config, IntEncoding};
+#[cfg(feature = "serde")]
+pub use de::{from_bitbuf, from_bitbuf_with, Deserializer};
+#[cfg(feature = "serde")]
+pub use ser::{to_bitbuf, to_bitbuf_with, Serializer};
 
 pub trait WriteToBitBuf {
-	fn write_to_bitbuf(&self, buf: &mut BitBuf);
-}
-
-pub trait FromBitBuf {
-    fn from_bitbuf(buf: &mut BitBuf) -> Self;
-}
-
-struct FourByte {
-    b1: u8,
-    b2: u8,
-    b3: u8,
-    b4: u8,
-}
+    fn write_to_bitbuf<W: BitWrite>(&self, buf: &mut W) -> Result<()>;
+
+    /// Writes `self` as a change from `baseline`, to be read back with
+    /// `FromBitBuf::read_delta` and the same baseline. The default writes
+    /// the whole value; integers, floats, strings and derived structs
+    /// write less when little has changed. Override both or neither.
+    fn write_delta<W: BitWrite>(&self, baseline: &Self, buf: &mut W) -> Result<()> {
+        let _ = baseline;
+        self.write_to_bitbuf(buf)
+    }
 
-impl FourByte {
-    pub fn trans_from_f32(value: f32) -> FourByte {
-        unsafe { transmute::<f32, FourByte>(value) } 
+    /// The number of bits `write_to_bitbuf` would write to a sink with
+    /// the default length prefix, measured with a `BitCounter` so that
+    /// nothing is encoded.
+    fn bit_len(&self) -> Result<usize> {
+        let mut counter = BitCounter::new();
+        self.write_to_bitbuf(&mut counter)?;
+        Ok(counter.bit_len())
     }
 
-    pub fn trans_to_f32(self) -> f32 {
-        unsafe { transmute::<FourByte, f32>(self) }
+    /// The number of bits `write_to_bitbuf` would write to `sink`,
+    /// following its length prefix and maximum length.
+    fn bit_len_for<W: BitWrite + ?Sized>(&self, sink: &W) -> Result<usize> {
+        let mut counter = BitCounter::for_sink(sink);
+        self.write_to_bitbuf(&mut counter)?;
+        Ok(counter.bit_len())
     }
 }
 
-struct EightByte {
-    b1: u8,
-    b2: u8,
-    b3: u8,
-    b4: u8,
-    b5: u8,
-    b6: u8,
-    b7: u8,
-    b8: u8,
-}
+pub trait FromBitBuf: Sized {
+    fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<Self>;
 
-impl EightByte {
-    pub fn trans_from_f64(value: f64) -> EightByte {
-        unsafe { transmute::<f64, EightByte>(value) }
+    /// Reads a value written by `WriteToBitBuf::write_delta` against
+    /// `baseline`.
+    fn read_delta<R: BitRead>(baseline: &Self, buf: &mut R) -> Result<Self> {
+        let _ = baseline;
+        Self::from_bitbuf(buf)
     }
+}
 
-    pub fn trans_to_f64(self) -> f64 {
-        unsafe { transmute::<EightByte, f64>(self) }
-    }
+/// The number of bits needed to hold every value in `0..=range`, for
+/// example 7 for 100. Zero needs no bits at all.
+pub const fn bits_required(range: u64) -> u8 {
+    (64 - range.leading_zeros()) as u8
 }
 
 #[derive(Clone)]
@@ -51,20 +95,47 @@ pub struct BitBuf {
     buf: Vec<u8>,
     pos: usize,       // The current bit position of the cursor.
     size: usize,      // Size in bits.
+    written: usize,   // The furthest bit position written to.
+    growable: bool,   // Whether writes past the end extend the buffer.
+    order: BitOrder,
+    length_prefix: LengthPrefix,
+    max_len: usize,   // The longest length accepted.
 }
 
 impl BitBuf {
 
+    /// Creates a new, empty BitBuf that grows as it is written to.
+    pub fn new() -> BitBuf {
+        BitBuf::with_capacity(0)
+    }
+
+    /// Creates a new, empty BitBuf that grows as it is written to,
+    /// reserving `capacity` bytes up front.
+    pub fn with_capacity(capacity: usize) -> BitBuf {
+        BitBuf {
+            buf: Vec::with_capacity(capacity),
+            pos: 0,
+            size: 0,
+            written: 0,
+            growable: true,
+            order: BitOrder::LsbFirst,
+            length_prefix: LengthPrefix::U32,
+            max_len: usize::MAX,
+        }
+    }
+
     /// Creates a new BitBuf, initializing a new Vec<u8>.
     /// for the underlying buffer.
     pub fn with_len(len: usize) -> BitBuf {
-        let mut vec = Vec::with_capacity(len);
-        unsafe { vec.set_len(len) };
-        for x in &mut vec { *x = 0; }
         BitBuf {
-            buf: vec,
+            buf: vec![0; len],
             pos: 0,
             size: len * 8,
+            written: 0,
+            growable: false,
+            order: BitOrder::LsbFirst,
+            length_prefix: LengthPrefix::U32,
+            max_len: usize::MAX,
         }
     }
 
@@ -73,451 +144,365 @@ impl BitBuf {
         self.buf
     }
 
-    /// Returns a slice into the underlying Vec<u8> buffer.
-    //pub fn buf_as_slice(&self) -> &[u8] {
-    //    self.buf.as_slice()
-    //}
-
-    /// The current bit size of the Vec<u8>.
-    pub fn bit_size(&self) -> usize {
-        self.size
-    }
-
-    /// The current position of the cursor. The BitBuf
-    /// does not insert, and will overwrite any data currently
-    /// at the cursor position during writing.
-    pub fn bit_pos(&self) -> usize {
-        self.pos
-    }
-
-    pub fn byte_pos(&self) -> usize {
-        self.pos / 8
-    }
-
-    pub fn can_write_bits(&self, bit_count: usize) -> bool {
-        (bit_count + self.pos) < self.size
-    }
-
-    pub fn can_read_bits(&self, bit_count: usize) -> bool {
-        (bit_count + self.pos) < self.size
+    /// Consumes the BitBuf, returning only the bytes up to the
+    /// furthest bit written. A trailing partial byte is zero padded.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf.truncate(self.written.div_ceil(8));
+        self.buf
     }
 
-    pub fn can_write_bytes(&self, byte_count: usize) -> bool {
-        self.can_write_bits(byte_count * 8)
+    /// Whether writing past the end grows the buffer.
+    pub fn is_growable(&self) -> bool {
+        self.growable
     }
 
-    pub fn can_read_bytes(&self, byte_count: usize) -> bool {
-        self.can_read_bits(byte_count * 8)
+    /// The order bits are packed in. Defaults to `BitOrder::LsbFirst`.
+    pub fn bit_order(&self) -> BitOrder {
+        self.order
     }
 
-    pub fn write_bool(&mut self, value: bool) {
-        self.in_write_byte((if value {1} else {0}), 1);
+    /// Changes the order that following reads and writes use.
+    pub fn set_bit_order(&mut self, order: BitOrder) {
+        self.order = order;
     }
 
-    pub fn read_bool(&mut self) -> bool {
-        self.in_read_byte(1) == 1
+    /// How lengths of strings, byte arrays and collections are written
+    /// and read. Defaults to `LengthPrefix::U32`. This is the same as
+    /// the `BitRead` and `BitWrite` methods.
+    pub fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
     }
 
-    pub fn write_i8(&mut self, value: i8) {
-        self.write_i8_part(value, 8);
+    /// Changes the length prefix of following reads and writes. Fixed
+    /// widths must be 1 to 64 bits. Readers must use the writer's.
+    pub fn set_length_prefix(&mut self, prefix: LengthPrefix) -> Result<()> {
+        prefix.check()?;
+        self.length_prefix = prefix;
+        Ok(())
     }
 
-    pub fn read_i8(&mut self) -> i8 {
-        self.read_i8_part(8)
+    /// The longest length accepted. Defaults to no limit.
+    pub fn max_len(&self) -> usize {
+        self.max_len
     }
 
-    fn write_i8_part(&mut self, value: i8, bits: u8) {
-        self.in_write_byte(value as u8, bits);
+    /// Changes the longest length accepted. Longer lengths are rejected
+    /// when writing, and when reading before anything is allocated.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
     }
 
-    fn read_i8_part(&mut self, bits: u8) -> i8 {
-        self.in_read_byte(bits) as i8
-    }
+    // Returns a slice into the underlying Vec<u8> buffer.
+    //pub fn buf_as_slice(&self) -> &[u8] {
+    //    self.buf.as_slice()
+    //}
 
-    pub fn write_u8(&mut self, value: u8) {
-        self.write_u8_part(value, 8);
+    /// The current bit size of the Vec<u8>.
+    pub fn bit_size(&self) -> usize {
+        self.size
     }
 
-    pub fn read_u8(&mut self) -> u8 {
-        self.read_u8_part(8)
+    /// The current position of the cursor. The BitBuf
+    /// does not insert, and will overwrite any data currently
+    /// at the cursor position during writing.
+    pub fn bit_pos(&self) -> usize {
+        self.pos
     }
 
-    pub fn write_u8_part(&mut self, value: u8, bits: u8) {
-        self.in_write_byte(value, bits);
+    pub fn byte_pos(&self) -> usize {
+        self.pos / 8
     }
 
-    pub fn read_u8_part(&mut self, bits: u8) -> u8 {
-        self.in_read_byte(bits)
+    /// Whether the cursor sits on a byte boundary. This is the same as
+    /// `BitRead::is_byte_aligned` and `BitWrite::is_write_aligned`, as
+    /// reads and writes share the cursor.
+    pub fn is_byte_aligned(&self) -> bool {
+        self.pos & 0x7 == 0
     }
 
-    pub fn write_u16(&mut self, value: u16) {
-        self.write_u16_part(value, 16);
+    /// Moves the cursor back to the start. This is the same as
+    /// `BitRead::rewind`, kept inherent so it does not clash with
+    /// `io::Seek::rewind` when both traits are in scope.
+    pub fn rewind(&mut self) -> Result<()> {
+        self.pos = 0;
+        Ok(())
     }
 
-    pub fn read_u16(&mut self) -> u16 {
-        self.read_u16_part(16)
+    pub fn can_write_bits(&self, bit_count: usize) -> bool {
+        self.growable || bit_count <= self.remaining_bits()
     }
 
-    pub fn write_u16_part(&mut self, value: u16, bits: u8) {
-        let a = (value >> 0) as u8;
-        let b = (value >> 8) as u8;
-
-        match (bits + 7) / 8 {
-            1 => {
-                self.in_write_byte(a, bits);
-            },
-            2 => {
-                self.in_write_byte(a, 8);
-                self.in_write_byte(b, bits - 8);
-            },
-            _ => {
-                //panic!("Must write between 1 and 32 bits.")
-            }
-        }
+    pub fn can_write_bytes(&self, byte_count: usize) -> bool {
+        self.growable || byte_count <= self.remaining_bits() / 8
     }
 
-    pub fn read_u16_part(&mut self, bits: u8) -> u16 {
-        let mut a = 0u16;
-        let mut b = 0u16;
-
-        match (bits + 7) / 8 {
-            1 => {
-                a = self.in_read_byte(bits) as u16;
+    /// Reads a string written by `write_string` without copying it,
+    /// borrowing from the buffer. The string data must start on a byte
+    /// boundary. On error the cursor does not move.
+    pub fn read_str(&mut self) -> Result<&str> {
+        let start = self.pos;
+        let len = self.read_len()?;
+        match raw::str_at(&self.buf, self.pos, self.size, len) {
+            Ok(string) => {
+                self.pos += len * 8;
+                Ok(string)
             },
-            2 => {
-                a = self.in_read_byte(8) as u16;
-                b = self.in_read_byte(bits - 8) as u16;
+            Err(err) => {
+                self.pos = start;
+                Err(err)
             },
-            _ => {
-                //panic!("Must read between 1 and 32 bits.")
-            }
         }
-
-        (a | (b << 8)) as u16
-    }
-
-    pub fn write_i16(&mut self, value: i16) {
-        self.write_i16_part(value, 16);
-    }
-
-    pub fn read_i16(&mut self) -> i16 {
-        self.read_i16_part(16)
-    }
-
-    fn write_i16_part(&mut self, value: i16, bits: u8) {
-        self.write_u16_part(value as u16, bits);
     }
 
-    fn read_i16_part(&mut self, bits: u8) -> i16 {
-        self.read_u16_part(bits) as i16
+    /// The CRC-8/SMBUS checksum of the bits in `range`. A trailing
+    /// partial byte is zero padded as `finish` would return it.
+    pub fn crc8(&self, range: Range<usize>) -> Result<u8> {
+        checksum::check_range(&range, self.size)?;
+        Ok(checksum::crc8(&self.buf, range, self.order))
     }
 
-    pub fn write_u32(&mut self, value: u32) {
-        self.write_u32_part(value, 32);
+    /// The CRC-16/CCITT-FALSE checksum of the bits in `range`.
+    pub fn crc16(&self, range: Range<usize>) -> Result<u16> {
+        checksum::check_range(&range, self.size)?;
+        Ok(checksum::crc16(&self.buf, range, self.order))
     }
 
-    pub fn read_u32(&mut self) -> u32 {
-        self.read_u32_part(32)
+    /// The CRC-32 checksum, as used by zlib, of the bits in `range`.
+    pub fn crc32(&self, range: Range<usize>) -> Result<u32> {
+        checksum::check_range(&range, self.size)?;
+        Ok(checksum::crc32(&self.buf, range, self.order))
     }
 
-    pub fn write_u32_part(&mut self, value: u32, bits: u8) {
-        let a = (value >> 0) as u8;
-        let b = (value >> 8) as u8;
-        let c = (value >> 16) as u8;
-        let d = (value >> 24) as u8;
-
-        match (bits + 7) / 8 {
-            1 => {
-                self.in_write_byte(a, bits);
-            },
-            2 => {
-                self.in_write_byte(a, 8);
-                self.in_write_byte(b, bits - 8);
-            },
-            3 => {
-                self.in_write_byte(a, 8);
-                self.in_write_byte(b, 8);
-                self.in_write_byte(c, bits - 16);
-            },
-            4 => {
-                self.in_write_byte(a, 8);
-                self.in_write_byte(b, 8);
-                self.in_write_byte(c, 8);
-                self.in_write_byte(d, bits - 24);
-            },
-            _ => {
-                //panic!("Must write between 1 and 32 bits.")
-            }
-        }
+    /// The Adler-32 checksum of the bits in `range`.
+    pub fn adler32(&self, range: Range<usize>) -> Result<u32> {
+        checksum::check_range(&range, self.size)?;
+        Ok(checksum::adler32(&self.buf, range, self.order))
     }
 
-    pub fn read_u32_part(&mut self, bits: u8) -> u32 {
-        let mut a = 0i32;
-        let mut b = 0i32;
-        let mut c = 0i32;
-        let mut d = 0i32;
-
-        match (bits + 7) / 8 {
-            1 => {
-                a = self.in_read_byte(bits) as i32;
-            },
-            2 => {
-                a = self.in_read_byte(8) as i32;
-                b = self.in_read_byte(bits - 8) as i32;
-            },
-            3 => {
-                a = self.in_read_byte(8) as i32;
-                b = self.in_read_byte(8) as i32;
-                c = self.in_read_byte(bits - 16) as i32;
-            },
-            4 => {
-                a = self.in_read_byte(8) as i32;
-                b = self.in_read_byte(8) as i32;
-                c = self.in_read_byte(8) as i32;
-                d = self.in_read_byte(bits - 24) as i32;
-            },
-            _ => {
-                //panic!("Must read between 1 and 32 bits.")
-            }
+    /// Zero pads the written bits to a byte boundary and appends a u32
+    /// CRC-32 of every byte before it, leaving the cursor at the end.
+    /// The checksum is then the last four bytes `finish` returns. On
+    /// error nothing is written and the cursor does not move.
+    pub fn append_crc32(&mut self) -> Result<()> {
+        let start = self.pos;
+        self.pos = self.written;
+        if let Err(err) = self.reserve_bits(raw::padding_bits(self.pos, 8) + 32) {
+            self.pos = start;
+            return Err(err);
         }
-
-        (a | (b << 8) | (c << 16) | (d << 24)) as u32
-    }
-
-    pub fn read_i32(&mut self) -> i32 {
-        self.read_i32_part(32)
-    }
-
-    pub fn write_i32(&mut self, value: i32) {
-        self.write_i32_part(value, 32);
+        self.pad_to_byte()?;
+        let crc = checksum::crc32(&self.buf, 0..self.pos, self.order);
+        self.write_u32(crc)
     }
 
-    fn write_i32_part(&mut self, value: i32, bits: u8) {
-        self.write_u32_part(value as u32, bits);
+    /// Checks the CRC-32 that `append_crc32` left in the last 32 bits
+    /// written. Buffers with fewer than 32 bits written are an error.
+    pub fn verify_crc32(&self) -> Result<bool> {
+        checksum::verify_crc32(&self.buf, self.written, self.order)
     }
 
-    fn read_i32_part(&mut self, bits: u8) -> i32 {
-        self.read_u32_part(bits) as i32
+    fn out_of_bounds(&self, needed: usize) -> BitBufError {
+        BitBufError::OutOfBounds { needed, remaining: self.remaining_bits() }
     }
 
-    pub fn write_u64(&mut self, value: u64) {
-        self.write_u64_part(value, 64);
-    }
-
-    pub fn read_u64(&mut self) -> u64 {
-        self.read_u64_part(64)
+    /// Writes `bits` (at most `max`) bits of `value`, checking the count
+    /// and bounds once for every typed write.
+    #[inline]
+    fn in_write_bits(&mut self, value: u64, bits: u8, max: u8) -> Result<()> {
+        raw::check_bit_count(bits, max)?;
+        self.reserve_bits(bits as usize)?;
+        raw::write_bits(&mut self.buf, self.pos, value, bits, self.order);
+        self.pos += bits as usize;
+        self.written = self.written.max(self.pos);
+        Ok(())
     }
 
-    pub fn write_u64_part(&mut self, value: u64, bits: u8) {
-        if bits <= 32 {
-            self.write_u32_part((value & 0xFFFFFFFF) as u32, bits);
-        } else {
-            self.write_u32_part(value as u32, 32);
-            self.write_u32_part((value >> 32) as u32, bits - 32);
+    /// Grows the buffer so that `bits` more fit at the cursor, or fails
+    /// if it is not growable.
+    fn grow(&mut self, bits: usize) -> Result<()> {
+        let end = match self.pos.checked_add(bits) {
+            Some(end) => end,
+            None => return Err(self.out_of_bounds(bits)),
+        };
+        if !self.growable {
+            return Err(self.out_of_bounds(bits));
         }
+        self.buf.resize(end.div_ceil(8), 0);
+        self.size = self.buf.len() * 8;
+        Ok(())
     }
+}
 
-    pub fn read_u64_part(&mut self, bits: u8) -> u64 {
-        if bits <= 32 {
-            self.read_u32_part(bits) as u64
-        } else {
-            let a = self.read_u32_part(32) as u64;
-            let b = self.read_u32_part(bits - 32) as u64;
-            a | (b << 32)
-        }
+impl BitWrite for BitBuf {
+    #[inline]
+    fn write_bits(&mut self, value: u64, bits: u8) -> Result<()> {
+        self.in_write_bits(value, bits, 64)
     }
 
-    pub fn write_i64(&mut self, value: i64) {
-        self.write_u64_part(value as u64, 64);
+    /// Checks that `bits` can be written at the cursor, growing the
+    /// buffer first if it is growable. Only the writes themselves move
+    /// the end that `finish` returns.
+    #[inline]
+    fn reserve_bits(&mut self, bits: usize) -> Result<()> {
+        if bits <= self.size - self.pos {
+            return Ok(());
+        }
+        self.grow(bits)
     }
 
-    pub fn read_i64(&mut self) -> i64 {
-        self.read_u64_part(64) as i64
+    fn bit_pos(&self) -> usize {
+        self.pos
     }
 
-    fn write_i64_part(&mut self, value: i64, bits: u8) {
-        self.write_u64_part(value as u64, bits);
+    fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
     }
 
-    fn read_i64_part(&mut self, bits: u8) -> i64 {
-        self.read_u64_part(bits) as i64
+    fn max_len(&self) -> usize {
+        self.max_len
     }
 
-    pub fn write_f32(&mut self, value: f32) {
-        let trans = FourByte::trans_from_f32(value);
-        self.in_write_byte(trans.b1, 8);
-        self.in_write_byte(trans.b2, 8);
-        self.in_write_byte(trans.b3, 8);
-        self.in_write_byte(trans.b4, 8);
+    #[inline]
+    fn write_u8_part(&mut self, value: u8, bits: u8) -> Result<()> {
+        self.in_write_bits(value as u64, bits, 8)
     }
 
-    pub fn read_f32(&mut self) -> f32 {
-        FourByte {
-            b1: self.in_read_byte(8),
-            b2: self.in_read_byte(8),
-            b3: self.in_read_byte(8),
-            b4: self.in_read_byte(8),
-        }.trans_to_f32()
+    #[inline]
+    fn write_u16_part(&mut self, value: u16, bits: u8) -> Result<()> {
+        self.in_write_bits(value as u64, bits, 16)
     }
 
-    pub fn write_f64(&mut self, value: f64) {
-        let trans = EightByte::trans_from_f64(value);
-        self.in_write_byte(trans.b1, 8);
-        self.in_write_byte(trans.b2, 8);
-        self.in_write_byte(trans.b3, 8);
-        self.in_write_byte(trans.b4, 8);
-        self.in_write_byte(trans.b5, 8);
-        self.in_write_byte(trans.b6, 8);
-        self.in_write_byte(trans.b7, 8);
-        self.in_write_byte(trans.b8, 8);
+    #[inline]
+    fn write_u32_part(&mut self, value: u32, bits: u8) -> Result<()> {
+        self.in_write_bits(value as u64, bits, 32)
     }
 
-    pub fn read_f64(&mut self) -> f64 {
-        EightByte {
-            b1: self.in_read_byte(8),
-            b2: self.in_read_byte(8),
-            b3: self.in_read_byte(8),
-            b4: self.in_read_byte(8),
-            b5: self.in_read_byte(8),
-            b6: self.in_read_byte(8),
-            b7: self.in_read_byte(8),
-            b8: self.in_read_byte(8),
-        }.trans_to_f64()
+    /// Writes every byte of `value`. Byte-aligned writes are a single
+    /// copy; otherwise the bytes are shifted in a word at a time.
+    #[inline]
+    fn write_u8_slice(&mut self, value: &[u8]) -> Result<()> {
+        self.reserve_bits(value.len().saturating_mul(8))?;
+        raw::write_bytes(&mut self.buf, self.pos, value, self.order);
+        self.pos += value.len() * 8;
+        self.written = self.written.max(self.pos);
+        Ok(())
     }
+}
 
-    pub fn write_u8_slice(&mut self, value: &[u8]) {
-        for i in 0..value.len() {
-            self.in_write_byte(value[i], 8);
+impl BitRead for BitBuf {
+    #[inline]
+    fn read_bits(&mut self, bits: u8) -> Result<u64> {
+        raw::check_bit_count(bits, 64)?;
+        if !self.can_read_bits(bits as usize) {
+            return Err(self.out_of_bounds(bits as usize));
         }
+        let value = raw::read_bits(&self.buf, self.pos, bits, self.order);
+        self.pos += bits as usize;
+        Ok(value)
     }
 
-    pub fn read_vec_u8(&mut self, length: usize) -> Vec<u8> {
-        (0..length).map(|_| self.in_read_byte(8)).collect()
+    fn remaining_bits(&self) -> usize {
+        self.size - self.pos
     }
 
-    pub fn write_string(&mut self, value: &str) {
-        self.write_u32(value.len() as u32);
-        self.write_u8_slice(value.as_bytes());
+    fn bit_pos(&self) -> usize {
+        self.pos
     }
 
-    pub fn read_string(&mut self) -> String {
-        let len = self.read_u32() as usize;
-        String::from_utf8(self.read_vec_u8(len)).unwrap()
+    fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
     }
 
-    #[inline(always)]
-    fn in_write_byte(&mut self, mut value: u8, bits: u8) {
-        value = value & (0xFF >> (8 - bits));
-
-        let p = (self.pos >> 3) as usize;
-        let bits_used = self.pos & 0x7;
-
-        if bits_used == 0 {
-            self.buf[p] = value;
-        } else {
-            let bits_free = 8 - bits_used;
-            let bits_left: i16 = bits_free as i16 - bits as i16;
+    fn max_len(&self) -> usize {
+        self.max_len
+    }
 
-            if bits_left >= 0 {
-                let mask = (0xFF >> bits_free) | (0xFF << (8 - bits_left));
-                self.buf[p] = (self.buf[p] & mask) | (value << bits_used);
-            } else {
-                self.buf[p] = (self.buf[p] & (0xFF >> bits_free)) | (value << bits_used);
-                self.buf[p + 1] = (self.buf[p + 1] & (0xFF << (bits - bits_free as u8))) | (value >> bits_free);
-            }
+    fn set_bit_pos(&mut self, pos: usize) -> Result<()> {
+        if pos > self.size {
+            return Err(self.out_of_bounds(pos - self.pos));
         }
-
-        self.pos += bits as usize;
+        self.pos = pos;
+        Ok(())
     }
 
-    #[inline(always)]
-    fn in_read_byte(&mut self, bits: u8) -> u8 {
-        let value: u8;
-        let p = (self.pos >> 3) as usize;
-        let bits_used = self.pos % 8;
-
-        if bits_used == 0 && bits == 8 {
-            value = self.buf[p];
-        } else {
-            let first = self.buf[p] >> bits_used;
-            let remainder = bits - (8 - bits_used as u8);
-            if remainder < 1 {
-                value = first & (0xFF >> (8 - bits));
-            } else {
-                let second = self.buf[p + 1] & (0xFF >> (8 - remainder));
-                value = first | (second << (bits - remainder));
-            }
+    fn read_into(&mut self, out: &mut [u8]) -> Result<()> {
+        let bits = out.len().saturating_mul(8);
+        if !self.can_read_bits(bits) {
+            return Err(self.out_of_bounds(bits));
         }
-
-        self.pos += bits as usize;
-        value
+        raw::read_bytes(&self.buf, self.pos, out, self.order);
+        self.pos += bits;
+        Ok(())
     }
-
 }
 
-
+impl Default for BitBuf {
+    fn default() -> BitBuf {
+        BitBuf::new()
+    }
+}
 
 #[test]
 fn bool_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = true;
-    buf.write_bool(testval);
+    buf.write_bool(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_bool() == testval);
+    assert!(buf.read_bool().unwrap() == testval);
 }
 
 #[test]
 fn u8_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 211;
-    buf.write_u8(testval);
+    buf.write_u8(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_u8() == testval);
+    assert!(buf.read_u8().unwrap() == testval);
 }
 
 #[test]
 fn u8_part_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 15;
-    buf.write_u8_part(testval, 4);
+    buf.write_u8_part(testval, 4).unwrap();
     buf.pos = 0;
-    assert!(buf.read_u8_part(4) == testval);
+    assert!(buf.read_u8_part(4).unwrap() == testval);
 }
 
 #[test]
 fn i8_part_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 6;
-    buf.write_i8_part(testval, 4);
+    buf.write_i8_part(testval, 4).unwrap();
     buf.pos = 0;
-    assert!(buf.read_i8_part(4) == testval);
+    assert!(buf.read_i8_part(4).unwrap() == testval);
 }
 
 #[test]
 fn i8_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = -109;
-    buf.write_i8(testval);
+    buf.write_i8(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_i8() == testval);
+    assert!(buf.read_i8().unwrap() == testval);
 }
 
 #[test]
 fn u16_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 34507;
-    buf.write_u16(testval);
+    buf.write_u16(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_u16() == testval);
+    assert!(buf.read_u16().unwrap() == testval);
 }
 
 #[test]
 fn u16_part_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 448;
-    buf.write_u16_part(testval, 13);
+    buf.write_u16_part(testval, 13).unwrap();
     buf.pos = 0;
-    let result = buf.read_u16_part(13);
+    let result = buf.read_u16_part(13).unwrap();
     println!("{}", result);
     assert!(result == testval);
 }
@@ -526,312 +511,740 @@ fn u16_part_writeread_equal() {
 fn i16_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = -11066;
-    buf.write_i16(testval);
+    buf.write_i16(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_i16() == testval);
+    assert!(buf.read_i16().unwrap() == testval);
 }
 
 #[test]
 fn i16_part_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 10034;
-    buf.write_i16_part(testval, 15);
+    buf.write_i16_part(testval, 15).unwrap();
     buf.pos = 0;
-    assert!(buf.read_i16_part(15) == testval);
+    assert!(buf.read_i16_part(15).unwrap() == testval);
 }
 
 #[test]
 fn u32_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 193772;
-    buf.write_u32(testval);
+    buf.write_u32(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_u32() == testval);
+    assert!(buf.read_u32().unwrap() == testval);
 }
 
 #[test]
 fn u32_part_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 839011;
-    buf.write_u32_part(testval, 27);
+    buf.write_u32_part(testval, 27).unwrap();
     buf.pos = 0;
-    assert!(buf.read_u32_part(27) == testval);
+    assert!(buf.read_u32_part(27).unwrap() == testval);
 }
 
 #[test]
 fn i32_part_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 54397;
-    buf.write_i32_part(testval, 22);
+    buf.write_i32_part(testval, 22).unwrap();
     buf.pos = 0;
-    assert!(buf.read_i32_part(22) == testval);
+    assert!(buf.read_i32_part(22).unwrap() == testval);
 }
 
 #[test]
 fn i32_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = -23498225;
-    buf.write_i32(testval);
+    buf.write_i32(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_i32() == testval);
+    assert!(buf.read_i32().unwrap() == testval);
 }
 
 #[test]
 fn u64_part_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 32944949231715;
-    buf.write_u64_part(testval, 59);
+    buf.write_u64_part(testval, 59).unwrap();
     buf.pos = 0;
-    assert!(buf.read_u64_part(59) == testval);
+    assert!(buf.read_u64_part(59).unwrap() == testval);
 }
 
 #[test]
 fn u64_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 248394023907611;
-    buf.write_u64(testval);
+    buf.write_u64(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_u64() == testval);
+    assert!(buf.read_u64().unwrap() == testval);
 }
 
 #[test]
 fn i64_part_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 1998372011;
-    buf.write_i64_part(testval, 50);
+    buf.write_i64_part(testval, 50).unwrap();
     buf.pos = 0;
-    assert!(buf.read_i64_part(50) == testval);
+    assert!(buf.read_i64_part(50).unwrap() == testval);
 }
 
 #[test]
 fn i64_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = -24839402390;
-    buf.write_i64(testval);
+    buf.write_i64(testval).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_i64().unwrap() == testval);
+}
+
+#[test]
+fn negative_part_writeread_equal() {
+    let mut buf = BitBuf::with_len(1400);
+    buf.write_i8_part(-8, 4).unwrap();
+    buf.write_i16_part(-10034, 15).unwrap();
+    buf.write_i32_part(-54397, 22).unwrap();
+    buf.write_i64_part(-1998372011, 50).unwrap();
+    buf.write_i32_part(-1, 1).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_i8_part(4).unwrap() == -8);
+    assert!(buf.read_i16_part(15).unwrap() == -10034);
+    assert!(buf.read_i32_part(22).unwrap() == -54397);
+    assert!(buf.read_i64_part(50).unwrap() == -1998372011);
+    assert!(buf.read_i32_part(1).unwrap() == -1);
+    assert!(buf.read_i32_part(0).unwrap() == 0);
+}
+
+#[test]
+fn signed_part_range_errors() {
+    let mut buf = BitBuf::with_len(16);
+    assert!(buf.write_i8_part(-9, 4) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_i8_part(8, 4) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_i16_part(i16::MIN, 15) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_i32_part(1, 1) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_i64_part(-1, 0) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_i8_part(0, 9) == Err(BitBufError::InvalidBitCount { bits: 9, max: 8 }));
+    assert!(buf.bit_pos() == 0);
+    buf.write_i8_part(-8, 4).unwrap();
+    buf.write_i64_part(i64::MIN, 64).unwrap();
+    buf.write_i32_part(0, 0).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_i8_part(4).unwrap() == -8);
+    assert!(buf.read_i64_part(64).unwrap() == i64::MIN);
+    assert!(buf.write_i32_sign_magnitude(0, 0) == Err(BitBufError::InvalidBitCount { bits: 0, max: 32 }));
+}
+
+#[test]
+fn sign_magnitude_writeread_equal() {
+    let mut buf = BitBuf::with_len(1400);
+    buf.write_i32_sign_magnitude(-5, 4).unwrap();
+    buf.write_i32_sign_magnitude(7, 4).unwrap();
+    buf.write_i64_sign_magnitude(-1998372011, 40).unwrap();
+    buf.write_i64_sign_magnitude(i64::MAX, 64).unwrap();
+    buf.write_i64_sign_magnitude(0, 1).unwrap();
+    buf.write_i32_sign_magnitude(0, 1).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_u8_part(4).unwrap() == 0b1101);
     buf.pos = 0;
-    assert!(buf.read_i64() == testval);
+    assert!(buf.read_i32_sign_magnitude(4).unwrap() == -5);
+    assert!(buf.read_i32_sign_magnitude(4).unwrap() == 7);
+    assert!(buf.read_i64_sign_magnitude(40).unwrap() == -1998372011);
+    assert!(buf.read_i64_sign_magnitude(64).unwrap() == i64::MAX);
+    assert!(buf.read_i64_sign_magnitude(1).unwrap() == 0);
+    assert!(buf.read_i32_sign_magnitude(1).unwrap() == 0);
+    assert!(buf.write_i32_sign_magnitude(1, 1) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_i32_sign_magnitude(8, 4) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_i64_sign_magnitude(i64::MIN, 64) == Err(BitBufError::ValueOutOfRange));
+}
+
+#[test]
+fn bits_required_equal() {
+    assert!(bits_required(0) == 0);
+    assert!(bits_required(1) == 1);
+    assert!(bits_required(3) == 2);
+    assert!(bits_required(4) == 3);
+    assert!(bits_required(100) == 7);
+    assert!(bits_required(u64::MAX) == 64);
+}
+
+#[test]
+fn ranged_writeread_equal() {
+    let mut buf = BitBuf::with_len(1400);
+    buf.write_ranged_u32(100, 0, 100).unwrap();
+    buf.write_ranged_u32(2, 0, 3).unwrap();
+    buf.write_ranged_u32(1500, 1500, 1500).unwrap();
+    buf.write_ranged_i32(-7, -10, 10).unwrap();
+    buf.write_ranged_u64(248394023907611, 248394023907000, 248394023908000).unwrap();
+    buf.write_ranged_i64(i64::MIN, i64::MIN, i64::MAX).unwrap();
+    assert!(buf.bit_pos() == 7 + 2 + 5 + 10 + 64);
+    buf.pos = 0;
+    assert!(buf.read_ranged_u32(0, 100).unwrap() == 100);
+    assert!(buf.read_ranged_u32(0, 3).unwrap() == 2);
+    assert!(buf.read_ranged_u32(1500, 1500).unwrap() == 1500);
+    assert!(buf.read_ranged_i32(-10, 10).unwrap() == -7);
+    assert!(buf.read_ranged_u64(248394023907000, 248394023908000).unwrap() == 248394023907611);
+    assert!(buf.read_ranged_i64(i64::MIN, i64::MAX).unwrap() == i64::MIN);
+}
+
+#[test]
+fn ranged_out_of_range_errors() {
+    let mut buf = BitBuf::with_len(1400);
+    assert!(buf.write_ranged_u32(101, 0, 100) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_ranged_i32(-11, -10, 10) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_ranged_u64(5, 10, 0) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.bit_pos() == 0);
+    buf.write_u8_part(127, 7).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_ranged_u32(0, 100) == Err(BitBufError::ValueOutOfRange));
+    buf.pos = 0;
+    assert!(buf.read_ranged_i32(-10, 100) == Err(BitBufError::ValueOutOfRange));
 }
 
 #[test]
 fn f32_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 3.0393124f32;
-    buf.write_f32(testval);
+    buf.write_f32(testval).unwrap();
     buf.pos = 0;
-    assert!(buf.read_f32() == testval);
+    assert!(buf.read_f32().unwrap() == testval);
 }
 
 #[test]
+#[allow(clippy::excessive_precision)]
 fn f64_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = 3.0395831239485302f64;
-    buf.write_f64(testval);
+    buf.write_f64(testval).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_f64().unwrap() == testval);
+}
+
+#[test]
+fn f32_quantized_writeread_equal() {
+    let mut buf = BitBuf::with_len(1400);
+    buf.write_f32_quantized(-100.0, -100.0, 100.0, 12).unwrap();
+    buf.write_f32_quantized(100.0, -100.0, 100.0, 12).unwrap();
+    buf.write_f32_quantized(33.3, -100.0, 100.0, 12).unwrap();
+    buf.write_f32_quantized(500.0, -100.0, 100.0, 12).unwrap();
+    buf.write_f32_quantized(f32::NAN, -100.0, 100.0, 12).unwrap();
+    buf.write_f32_quantized(0.123456, 0.0, 1.0, 32).unwrap();
+    assert!(buf.bit_pos() == 5 * 12 + 32);
+    buf.pos = 0;
+    assert!(buf.read_f32_quantized(-100.0, 100.0, 12).unwrap() == -100.0);
+    assert!(buf.read_f32_quantized(-100.0, 100.0, 12).unwrap() == 100.0);
+    assert!((buf.read_f32_quantized(-100.0, 100.0, 12).unwrap() - 33.3).abs() <= 200.0 / 4095.0 / 2.0);
+    assert!(buf.read_f32_quantized(-100.0, 100.0, 12).unwrap() == 100.0);
+    assert!(buf.read_f32_quantized(-100.0, 100.0, 12).unwrap() == -100.0);
+    assert!(buf.read_f32_quantized(0.0, 1.0, 32).unwrap() == 0.123456);
+}
+
+#[test]
+fn f32_resolution_writeread_equal() {
+    let mut buf = BitBuf::with_len(1400);
+    buf.write_f32_resolution(271.828, 0.0, 360.0, 0.1).unwrap();
+    assert!(buf.bit_pos() == 12);
+    buf.write_f32_resolution(0.5, 0.0, 1.0, 1.0).unwrap();
+    assert!(buf.bit_pos() == 13);
     buf.pos = 0;
-    assert!(buf.read_f64() == testval);
+    assert!((buf.read_f32_resolution(0.0, 360.0, 0.1).unwrap() - 271.828).abs() <= 0.05);
+    assert!(buf.read_f32_resolution(0.0, 1.0, 1.0).unwrap() == 1.0);
+}
+
+#[test]
+fn f32_quantized_invalid_errors() {
+    let mut buf = BitBuf::with_len(1400);
+    assert!(buf.write_f32_quantized(1.0, 0.0, 1.0, 0).is_err());
+    assert!(buf.write_f32_quantized(1.0, 0.0, 1.0, 33).is_err());
+    assert!(buf.write_f32_quantized(1.0, 1.0, 0.0, 8) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.write_f32_resolution(1.0, 0.0, 1.0, 0.0) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.read_f32_resolution(0.0, 1e10, 1e-10).is_err());
+    assert!(buf.bit_pos() == 0);
 }
 
 #[test]
 fn string_writeread_equal() {
     let mut buf = BitBuf::with_len(1400);
     let testval = "This is a test string. Nothing to see here. No, really!";
-    buf.write_string(testval);
-    buf.pos = 0;
-    assert!(buf.read_string() == testval);
-}
-
-//struct BenchPerson {
-//    first_name: String,
-//    last_name: String,
-//    age: i8,
-//    alive: bool,
-//    weight: i16,
-//}
-//
-//impl WriteToBitBuf for BenchPerson {
-//    fn write_to_bitbuf(&self, buf: &mut BitBuf) {
-//        buf.write_string(&self.first_name);
-//        buf.write_string(&self.last_name);
-//        buf.write_i8(self.age);
-//        buf.write_bool(self.alive);
-//        buf.write_i16(self.weight);
-//    }
-//}
-//
-//impl FromBitBuf for BenchPerson {
-//    fn from_bitbuf(buf: &mut BitBuf) -> BenchPerson {
-//        BenchPerson {
-//            first_name: buf.read_string(),
-//            last_name: buf.read_string(),
-//            age: buf.read_i8(),
-//            alive: buf.read_bool(),
-//            weight: buf.read_i16(),
-//        }
-//    }
-//}
-//#[bench]
-//fn benchperson_write1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    let person = BenchPerson {
-//        first_name: String::from_str("John"),
-//        last_name: String::from_str("Johnson"),
-//        age: 47,
-//        alive: true,
-//        weight: 203,
-//    };
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..63 {
-//            person.write_to_bitbuf(&mut buf);
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn benchperson_read1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    let person = BenchPerson {
-//        first_name: String::from_str("John"),
-//        last_name: String::from_str("Johnson"),
-//        age: 47,
-//        alive: true,
-//        weight: 203,
-//    };
-//    for _ in 0..63 {
-//        person.write_to_bitbuf(&mut buf);
-//    }
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..63 {
-//            let p: BenchPerson = FromBitBuf::from_bitbuf(&mut buf);
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn bitbuf_create_bench(b: &mut Bencher) {
-//    b.iter(|| {
-//        let mut buf = BitBuf::with_len(1400);
-//    })
-//}
-//
-//#[bench]
-//fn in_byte_write1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..1400 {
-//            buf.in_write_byte(240, 8);
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn in_byte_read1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    for _ in 0..1400 {
-//            buf.in_write_byte(240, 8);
-//    }
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..1400 {
-//            let b = buf.in_read_byte(8);
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn string_write1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..50 {
-//            buf.write_string("This is a string. Woo!!!");
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn string_read1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    for _ in 0..50 {
-//        buf.write_string("This is a string. Woo!!!");
-//    }
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..50 {
-//            let s = buf.read_string();
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn i32_write1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..350 {
-//            buf.write_i32(123239012);
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn i64_write1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..175 {
-//            buf.write_i64(12352390123458);
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn i64_read1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    for _ in 0..175 {
-//        buf.write_i64(12352390123458);
-//    }
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..175 {
-//            let i = buf.read_i64();
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn f32_write1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..350 {
-//            buf.write_f32(123.239012f32);
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn f64_write1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..175 {
-//            buf.write_f64(1235.2390123458f64);
-//        }
-//    })
-//}
-//
-//#[bench]
-//fn f64_read1400_bench(b: &mut Bencher) {
-//    let mut buf = BitBuf::with_len(1400);
-//    for _ in 0..175 {
-//        buf.write_f64(1235.2390123458f64);
-//    }
-//    b.iter(|| {
-//        buf.pos = 0;
-//        for _ in 0..175 {
-//            let f = buf.read_f64();
-//        }
-//    })
-//}
+    buf.write_string(testval).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_string().unwrap() == testval);
+}
+
+#[test]
+fn var_u32_writeread_equal() {
+    let mut buf = BitBuf::with_len(1400);
+    for &testval in &[0, 1, 127, 128, 300, 193772, u32::MAX] {
+        buf.write_var_u32(testval).unwrap();
+    }
+    buf.pos = 0;
+    for &testval in &[0, 1, 127, 128, 300, 193772, u32::MAX] {
+        assert!(buf.read_var_u32().unwrap() == testval);
+    }
+}
+
+#[test]
+fn var_u64_matches_leb128() {
+    let mut buf = BitBuf::new();
+    buf.write_var_u64(624485).unwrap();
+    buf.write_var_u64(u64::MAX).unwrap();
+    let bytes = buf.finish();
+    assert!(bytes[..3] == [0xE5, 0x8E, 0x26]);
+    assert!(bytes.len() == 13);
+    let mut reader = BitReader::new(&bytes);
+    assert!(reader.read_var_u64().unwrap() == 624485);
+    assert!(reader.read_var_u64().unwrap() == u64::MAX);
+}
+
+#[test]
+fn var_i64_writeread_equal() {
+    let mut buf = BitBuf::new();
+    for &testval in &[0, -1, 1, -64, 64, -24839402390, i64::MIN, i64::MAX] {
+        buf.write_var_i64(testval).unwrap();
+        buf.write_var_i32(testval as i32).unwrap();
+    }
+    assert!(buf.pos > 0);
+    buf.pos = 0;
+    for &testval in &[0, -1, 1, -64, 64, -24839402390, i64::MIN, i64::MAX] {
+        assert!(buf.read_var_i64().unwrap() == testval);
+        assert!(buf.read_var_i32().unwrap() == testval as i32);
+    }
+}
+
+#[test]
+fn var_chunked_writeread_equal() {
+    let mut buf = BitBuf::new();
+    buf.write_var_u32_chunked(5, 3).unwrap();
+    assert!(buf.bit_pos() == 4);
+    buf.write_var_u32_chunked(9, 3).unwrap();
+    assert!(buf.bit_pos() == 12);
+    buf.write_var_u64_chunked(248394023907611, 13).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_var_u32_chunked(3).unwrap() == 5);
+    assert!(buf.read_var_u32_chunked(3).unwrap() == 9);
+    assert!(buf.read_var_u64_chunked(13).unwrap() == 248394023907611);
+    assert!(buf.write_var_u32_chunked(1, 0).is_err());
+    assert!(buf.read_var_u32_chunked(32).is_err());
+}
+
+#[test]
+fn var_overflow_errors() {
+    let mut buf = BitBuf::new();
+    buf.write_var_u64(u32::MAX as u64 + 1).unwrap();
+    buf.write_u8_slice(&[0xFF; 10]).unwrap();
+    buf.write_u8(0x01).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_var_u32() == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.bit_pos() == 0);
+    buf.pos = 40;
+    assert!(buf.read_var_u64() == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.bit_pos() == 40);
+}
+
+#[test]
+fn var_truncated_errors() {
+    let bytes = [0xFF; 3];
+    let mut reader = BitReader::new(&bytes);
+    assert!(reader.read_var_u32().is_err());
+    assert!(reader.bit_pos() == 0);
+    assert!(reader.read_var_i64().is_err());
+    assert!(reader.read_var_u32_chunked(5).is_err());
+    assert!(reader.bit_pos() == 0);
+}
+
+#[test]
+fn msb_first_layout_equal() {
+    let mut buf = BitBuf::new();
+    buf.set_bit_order(BitOrder::MsbFirst);
+    buf.write_u8_part(0b101, 3).unwrap();
+    buf.write_u8_part(0b11111, 5).unwrap();
+    buf.write_u16(0x1234).unwrap();
+    buf.write_u8_part(0xD, 4).unwrap();
+    buf.write_u16_part(0xABC, 12).unwrap();
+    buf.write_u32(0xDEADBEEF).unwrap();
+    assert!(buf.finish() == vec![0xBF, 0x12, 0x34, 0xDA, 0xBC, 0xDE, 0xAD, 0xBE, 0xEF]);
+}
+
+#[test]
+fn msb_first_writeread_equal() {
+    let mut buf = BitBuf::with_len(1400);
+    buf.set_bit_order(BitOrder::MsbFirst);
+    buf.write_bool(true).unwrap();
+    buf.write_i32_part(-54397, 22).unwrap();
+    buf.write_u64_part(32944949231715, 59).unwrap();
+    buf.write_f64(3.03958312394853f64).unwrap();
+    buf.write_string("Big end first.").unwrap();
+    buf.write_var_i64(-24839402390).unwrap();
+    buf.write_i32_sign_magnitude(-5, 4).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_bool().unwrap());
+    assert!(buf.read_i32_part(22).unwrap() == -54397);
+    assert!(buf.read_u64_part(59).unwrap() == 32944949231715);
+    assert!(buf.read_f64().unwrap() == 3.03958312394853f64);
+    assert!(buf.read_string().unwrap() == "Big end first.");
+    assert!(buf.read_var_i64().unwrap() == -24839402390);
+    assert!(buf.read_i32_sign_magnitude(4).unwrap() == -5);
+}
+
+#[test]
+fn u8_slice_unaligned_writeread_equal() {
+    let bytes: Vec<u8> = (0..100).map(|i| (i * 7) as u8).collect();
+    let mut buf = BitBuf::with_len(1400);
+    buf.write_u8_part(5, 3).unwrap();
+    buf.write_u8_slice(&bytes).unwrap();
+    buf.write_u8_slice(&bytes[..13]).unwrap();
+    buf.pos = 0;
+    let mut out = [0; 13];
+    assert!(buf.read_u8_part(3).unwrap() == 5);
+    assert!(buf.read_vec_u8(100).unwrap() == bytes);
+    buf.read_into(&mut out).unwrap();
+    assert!(out[..] == bytes[..13]);
+}
+
+#[test]
+fn seek_skip_rewind_moves_cursor() {
+    use std::io::SeekFrom;
+
+    let mut buf = BitBuf::with_len(4);
+    buf.write_u8_part(5, 3).unwrap();
+    buf.write_u16(0xBEEF).unwrap();
+    buf.rewind().unwrap();
+    buf.skip_bits(3).unwrap();
+    assert!(buf.read_u16().unwrap() == 0xBEEF);
+    assert!(buf.seek_bits(SeekFrom::Current(-16)).unwrap() == 3);
+    assert!(buf.read_u16().unwrap() == 0xBEEF);
+    assert!(buf.seek_bits(SeekFrom::End(-8)).unwrap() == 24);
+    assert!(buf.remaining_bits() == 8);
+    assert!(buf.seek_bits(SeekFrom::Start(0)).unwrap() == 0);
+    buf.skip_bytes(4).unwrap();
+    assert!(buf.remaining_bits() == 0);
+    assert!(buf.skip_bits(1) == Err(BitBufError::OutOfBounds { needed: 1, remaining: 0 }));
+    assert!(buf.seek_bits(SeekFrom::Current(-33)).is_err());
+    assert!(buf.seek_bits(SeekFrom::Start(33)).is_err());
+    assert!(buf.bit_pos() == 32);
+
+    // The end is the buffer's size, however little has been written.
+    let mut buf = BitBuf::with_len(4);
+    buf.write_u8(1).unwrap();
+    assert!(buf.seek_bits(SeekFrom::End(0)).unwrap() == 32);
+}
+
+#[test]
+fn delta_writeread_equal() {
+    let mut buf = BitBuf::new();
+    buf.write_delta_u32(1000, 1000).unwrap();
+    assert!(buf.bit_pos() == 1);
+    buf.write_delta_i16(-3, 4).unwrap();
+    assert!(buf.bit_pos() == 1 + 6);
+    buf.write_delta_u8(0, 255).unwrap();
+    buf.write_delta_u64(0, u64::MAX).unwrap();
+    buf.write_delta_i64(i64::MIN, i64::MAX).unwrap();
+    buf.write_delta_f32(1.5001, 1.5).unwrap();
+    buf.write_delta_f64(-0.0, 0.0).unwrap();
+    buf.rewind().unwrap();
+    assert!(buf.read_delta_u32(1000).unwrap() == 1000);
+    assert!(buf.read_delta_i16(4).unwrap() == -3);
+    assert!(buf.read_delta_u8(255).unwrap() == 0);
+    assert!(buf.read_delta_u64(u64::MAX).unwrap() == 0);
+    assert!(buf.read_delta_i64(i64::MAX).unwrap() == i64::MIN);
+    assert!(buf.read_delta_f32(1.5).unwrap() == 1.5001);
+    assert!(buf.read_delta_f64(0.0).unwrap().is_sign_negative());
+}
+
+#[test]
+fn delta_out_of_range_errors() {
+    let mut buf = BitBuf::new();
+    buf.write_delta_u16(300, 0).unwrap();
+    buf.rewind().unwrap();
+    assert!(buf.read_delta_u8(0) == Err(BitBufError::ValueOutOfRange));
+    assert!(buf.bit_pos() == 0);
+}
+
+#[test]
+fn delta_truncated_errors() {
+    let mut buf = BitBuf::new();
+    buf.write_delta_i32(-100000, 0).unwrap();
+    let bytes = buf.finish();
+    let mut reader = BitReader::new(&bytes[..2]);
+    assert!(reader.read_delta_i32(0).is_err());
+    assert!(reader.bit_pos() == 0);
+    assert!(reader.read_delta_i64(0).is_err());
+    assert!(reader.read_delta_f64(0.0).is_err());
+    assert!(reader.bit_pos() == 0);
+}
+
+#[test]
+fn peek_leaves_cursor() {
+    let mut buf = BitBuf::with_len(2);
+    buf.write_bool(true).unwrap();
+    buf.write_u8_part(5, 3).unwrap();
+    buf.write_u16_part(0x0ABC, 12).unwrap();
+    buf.rewind().unwrap();
+    assert!(buf.peek_bool().unwrap());
+    assert!(buf.peek_u8_part(4).unwrap() == 0xB);
+    assert!(buf.peek_u32_part(16).unwrap() == 0xABCB);
+    assert!(buf.peek_u64_part(17).is_err());
+    assert!(buf.bit_pos() == 0);
+    buf.skip_bits(4).unwrap();
+    assert!(buf.peek_bits(12).unwrap() == 0x0ABC);
+    assert!(buf.read_u16_part(12).unwrap() == 0x0ABC);
+}
+
+#[test]
+fn padding_writeread_equal() {
+    let mut buf = BitBuf::with_len(16);
+    buf.write_u8_slice(&[0xFF; 16]).unwrap();
+    buf.rewind().unwrap();
+    buf.write_u8_part(5, 3).unwrap();
+    assert!(!buf.is_byte_aligned());
+    buf.pad_to_byte().unwrap();
+    assert!(buf.is_byte_aligned() && buf.bit_pos() == 8);
+    buf.pad_to_byte().unwrap();
+    assert!(buf.bit_pos() == 8);
+    buf.write_bool(true).unwrap();
+    buf.pad_to(96).unwrap();
+    assert!(buf.bit_pos() == 96);
+    assert!(buf.pad_to(256).is_err());
+    assert!(buf.bit_pos() == 96);
+
+    buf.rewind().unwrap();
+    assert!(buf.read_u8_part(3).unwrap() == 5);
+    buf.align_to_byte_checked().unwrap();
+    assert!(buf.read_bool().unwrap());
+    buf.align_to_checked(96).unwrap();
+    buf.skip_bits(3).unwrap();
+    assert!(buf.align_to_byte_checked() == Err(BitBufError::InvalidPadding));
+    assert!(buf.bit_pos() == 99);
+    buf.align_to_byte().unwrap();
+    assert!(buf.bit_pos() == 104);
+    buf.align_to(0).unwrap();
+    assert!(buf.bit_pos() == 104);
+}
+
+#[test]
+fn write_past_end_errors() {
+    let mut buf = BitBuf::with_len(2);
+    buf.write_u8_part(0, 3).unwrap();
+    assert!(buf.write_u16(1) == Err(BitBufError::OutOfBounds { needed: 16, remaining: 13 }));
+    assert!(buf.bit_pos() == 3);
+    buf.write_u16_part(1, 13).unwrap();
+    assert!(buf.write_bool(true).is_err());
+}
+
+#[test]
+fn read_past_end_errors() {
+    let mut buf = BitBuf::with_len(4);
+    assert!(buf.read_u64().is_err());
+    assert!(buf.read_vec_u8(5).is_err());
+    assert!(buf.read_u32().unwrap() == 0);
+    assert!(buf.read_bool() == Err(BitBufError::OutOfBounds { needed: 1, remaining: 0 }));
+}
+
+#[test]
+fn invalid_bit_count_errors() {
+    let mut buf = BitBuf::with_len(16);
+    assert!(buf.write_u8_part(1, 9) == Err(BitBufError::InvalidBitCount { bits: 9, max: 8 }));
+    assert!(buf.read_u32_part(33) == Err(BitBufError::InvalidBitCount { bits: 33, max: 32 }));
+}
+
+#[test]
+fn string_bad_length_errors() {
+    let mut buf = BitBuf::with_len(16);
+    buf.write_u32(13).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_string() == Err(BitBufError::InvalidLength(13)));
+    assert!(buf.bit_pos() == 0);
+}
+
+#[test]
+fn string_invalid_utf8_errors() {
+    let mut buf = BitBuf::with_len(16);
+    buf.write_u32(2).unwrap();
+    buf.write_u8_slice(&[0xC3, 0x28]).unwrap();
+    buf.pos = 0;
+    match buf.read_string() {
+        Err(BitBufError::InvalidUtf8(_)) => {},
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(buf.bit_pos() == 0);
+}
+
+#[test]
+fn read_str_borrows_equal() {
+    let mut buf = BitBuf::new();
+    buf.write_string("In place.").unwrap();
+    buf.write_bool(true).unwrap();
+    buf.write_string("Unaligned.").unwrap();
+    buf.rewind().unwrap();
+    assert!(buf.read_str().unwrap() == "In place.");
+    assert!(buf.read_bool().unwrap());
+    assert!(buf.read_str() == Err(BitBufError::NotByteAligned));
+    assert!(buf.bit_pos() == 32 + 9 * 8 + 1);
+    assert!(buf.read_string().unwrap() == "Unaligned.");
+}
+
+#[test]
+fn read_string_lossy_replaces_invalid() {
+    let mut buf = BitBuf::new();
+    buf.write_bytes(&[b'o', 0xC3, 0x28, b'k']).unwrap();
+    buf.rewind().unwrap();
+    assert!(buf.read_string_lossy().unwrap() == "o\u{FFFD}(k");
+}
+
+#[test]
+fn read_string_into_reuses_allocation() {
+    let mut buf = BitBuf::new();
+    buf.write_string("A longer first message.").unwrap();
+    buf.write_string("Second.").unwrap();
+    buf.write_bytes(&[0xC3, 0x28]).unwrap();
+    buf.rewind().unwrap();
+
+    let mut out = String::new();
+    buf.read_string_into(&mut out).unwrap();
+    assert!(out == "A longer first message.");
+    let capacity = out.capacity();
+    buf.read_string_into(&mut out).unwrap();
+    assert!(out == "Second." && out.capacity() == capacity);
+
+    let pos = buf.bit_pos();
+    match buf.read_string_into(&mut out) {
+        Err(BitBufError::InvalidUtf8(_)) => {},
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(out.is_empty() && buf.bit_pos() == pos);
+}
+
+#[test]
+fn length_prefix_writeread_equal() {
+    let bytes = [7u8; 200];
+    for &prefix in &[LengthPrefix::U8, LengthPrefix::U16, LengthPrefix::Varint] {
+        let mut buf = BitBuf::new();
+        buf.set_length_prefix(prefix).unwrap();
+        buf.write_string("short").unwrap();
+        buf.write_bytes(&bytes).unwrap();
+        buf.rewind().unwrap();
+        assert!(buf.read_string().unwrap() == "short");
+        assert!(buf.read_bytes().unwrap() == bytes[..]);
+    }
+
+    let mut buf = BitBuf::new();
+    buf.set_length_prefix(LengthPrefix::Varint).unwrap();
+    buf.write_string("short").unwrap();
+    buf.write_bytes(&bytes).unwrap();
+    assert!(buf.bit_pos() == (8 + 5 * 8) + (16 + 200 * 8));
+}
+
+#[test]
+fn length_prefix_too_narrow_errors() {
+    let mut buf = BitBuf::new();
+    buf.set_length_prefix(LengthPrefix::U8).unwrap();
+    assert!(buf.write_bytes(&[0; 256]) == Err(BitBufError::InvalidLength(256)));
+    assert!(buf.bit_pos() == 0);
+}
+
+#[test]
+fn max_len_errors() {
+    let mut buf = BitBuf::new();
+    buf.write_string("twelve bytes").unwrap();
+    buf.set_max_len(11);
+    assert!(buf.write_string("twelve bytes") == Err(BitBufError::InvalidLength(12)));
+    assert!(buf.write_bytes(&[0; 11]).is_ok());
+
+    // A hostile length is rejected before it is allocated.
+    let mut bytes = vec![0xFF; 4];
+    bytes.extend_from_slice(&buf.finish());
+    let mut reader = BitReader::new(&bytes);
+    reader.set_max_len(1 << 20);
+    assert!(reader.read_bytes() == Err(BitBufError::InvalidLength(u32::MAX as usize)));
+    assert!(reader.bit_pos() == 0);
+    reader.set_bit_pos(32).unwrap();
+    reader.set_max_len(11);
+    assert!(reader.read_string() == Err(BitBufError::InvalidLength(12)));
+    assert!(reader.bit_pos() == 32);
+}
+
+#[test]
+fn growable_writeread_equal() {
+    let mut buf = BitBuf::new();
+    buf.write_bool(true).unwrap();
+    buf.write_string("Grows as needed.").unwrap();
+    buf.write_u64_part(32944949231715, 59).unwrap();
+    assert!(buf.bit_size() == buf.bit_pos().div_ceil(8) * 8);
+    buf.pos = 0;
+    assert!(buf.read_bool().unwrap());
+    assert!(buf.read_string().unwrap() == "Grows as needed.");
+    assert!(buf.read_u64_part(59).unwrap() == 32944949231715);
+    assert!(buf.read_u8().is_err());
+}
+
+#[test]
+fn append_crc32_verify_equal() {
+    for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+        let mut buf = BitBuf::new();
+        buf.set_bit_order(order);
+        buf.write_u32_part(839011, 27).unwrap();
+        buf.write_string("Checked.").unwrap();
+        buf.rewind().unwrap();
+        buf.append_crc32().unwrap();
+        assert!(buf.verify_crc32().unwrap());
+        let crc = buf.crc32(0..buf.bit_pos() - 32).unwrap();
+
+        let mut bytes = buf.finish();
+        let n = bytes.len();
+        let tail = match order {
+            BitOrder::LsbFirst => crc.to_le_bytes(),
+            BitOrder::MsbFirst => crc.to_be_bytes(),
+        };
+        assert!(bytes[n - 4..] == tail);
+        assert!(BitReader::with_bit_order(&bytes, order).verify_crc32().unwrap());
+        bytes[1] ^= 0x10;
+        assert!(!BitReader::with_bit_order(&bytes, order).verify_crc32().unwrap());
+    }
+    assert!(BitBuf::new().verify_crc32().is_err());
+}
+
+#[test]
+fn checksum_range_errors() {
+    let mut buf = BitBuf::with_len(2);
+    buf.write_u16(0xBEEF).unwrap();
+    let mut tail = BitBuf::new();
+    tail.write_u16(0x0EEF).unwrap();
+    assert!(buf.crc8(0..12).unwrap() == tail.crc8(0..16).unwrap());
+    assert!(buf.crc16(8..17).is_err());
+    assert!(buf.adler32(0..16).unwrap() == 0x029E01AE);
+    assert!(buf.append_crc32() == Err(BitBufError::OutOfBounds { needed: 32, remaining: 0 }));
+    assert!(buf.bit_pos() == 16);
+}
+
+#[test]
+fn finish_returns_written_bytes() {
+    let mut buf = BitBuf::with_capacity(64);
+    buf.write_u16(0xBEEF).unwrap();
+    buf.write_u8_part(5, 3).unwrap();
+    assert!(buf.finish() == vec![0xEF, 0xBE, 0x05]);
+
+    let mut buf = BitBuf::with_len(1400);
+    buf.write_u32(0xDEADBEEF).unwrap();
+    buf.pos = 0;
+    buf.write_u8(0xAA).unwrap();
+    assert!(buf.finish() == vec![0xAA, 0xBE, 0xAD, 0xDE]);
+}
+
+#[test]
+fn reserve_bits_writes_nothing() {
+    let mut buf = BitBuf::new();
+    buf.reserve_bits(64).unwrap();
+    assert!(buf.clone().finish().is_empty());
+    buf.write_u8(0xAB).unwrap();
+    buf.reserve_bits(64).unwrap();
+    assert!(buf.bit_size() == 72);
+    assert!(buf.finish() == vec![0xAB]);
+}
+
+#[test]
+fn overwrite_preserves_neighbouring_bits() {
+    let mut buf = BitBuf::with_len(2);
+    buf.write_u16(0xFFFF).unwrap();
+    buf.pos = 5;
+    buf.write_u8_part(0, 4).unwrap();
+    buf.pos = 0;
+    assert!(buf.read_u16().unwrap() == 0xFE1F);
+}
@@ -0,0 +1,260 @@
+use error::{BitBufError, Result};
+use raw::{self, BitOrder, LengthPrefix};
+use read::BitRead;
+use write::BitWrite;
+
+/// A growable FIFO of bits with separate read and write cursors. Writes
+/// append at the write cursor and reads consume from the read cursor, so
+/// one buffer can carry a stream of messages. Call `compact` now and
+/// then to drop consumed bytes.
+#[derive(Clone, Debug)]
+pub struct BitQueue {
+    buf: Vec<u8>,
+    read_pos: usize,  // The next bit to read.
+    write_pos: usize, // The next bit to write; the end of the readable bits.
+    order: BitOrder,
+    length_prefix: LengthPrefix,
+    max_len: usize,   // The longest length accepted.
+}
+
+impl BitQueue {
+
+    /// Creates a new, empty BitQueue.
+    pub fn new() -> BitQueue {
+        BitQueue::with_capacity(0)
+    }
+
+    /// Creates a new, empty BitQueue, reserving `capacity` bytes up front.
+    pub fn with_capacity(capacity: usize) -> BitQueue {
+        BitQueue {
+            buf: Vec::with_capacity(capacity),
+            read_pos: 0,
+            write_pos: 0,
+            order: BitOrder::LsbFirst,
+            length_prefix: LengthPrefix::U32,
+            max_len: usize::MAX,
+        }
+    }
+
+    /// The number of bits written but not yet read.
+    pub fn readable_bits(&self) -> usize {
+        self.write_pos - self.read_pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read_pos == self.write_pos
+    }
+
+    /// The bit position of the read cursor. Positions are counted from
+    /// the start of the buffer, so `compact` moves them.
+    pub fn read_pos(&self) -> usize {
+        self.read_pos
+    }
+
+    /// The bit position of the write cursor.
+    pub fn write_pos(&self) -> usize {
+        self.write_pos
+    }
+
+    /// The order bits are packed in. Defaults to `BitOrder::LsbFirst`.
+    pub fn bit_order(&self) -> BitOrder {
+        self.order
+    }
+
+    /// Changes the order that following reads and writes use.
+    pub fn set_bit_order(&mut self, order: BitOrder) {
+        self.order = order;
+    }
+
+    /// The length prefix of both cursors, as for `BitBuf`.
+    pub fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
+    }
+
+    /// Changes the length prefix, as `BitBuf::set_length_prefix`.
+    pub fn set_length_prefix(&mut self, prefix: LengthPrefix) -> Result<()> {
+        prefix.check()?;
+        self.length_prefix = prefix;
+        Ok(())
+    }
+
+    /// The longest length accepted, as for `BitBuf`.
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Changes the longest length accepted, as `BitBuf::set_max_len`.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// Drops every byte before the read cursor, moving the unread bits
+    /// to the front of the buffer and both cursors back by the same
+    /// amount. The read cursor keeps its offset within its byte.
+    pub fn compact(&mut self) {
+        if self.read_pos == self.write_pos {
+            self.clear();
+            return;
+        }
+        let consumed = self.read_pos / 8;
+        self.buf.drain(..consumed);
+        self.read_pos -= consumed * 8;
+        self.write_pos -= consumed * 8;
+    }
+
+    /// Discards all bits, keeping the allocation.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.read_pos = 0;
+        self.write_pos = 0;
+    }
+}
+
+impl Default for BitQueue {
+    fn default() -> BitQueue {
+        BitQueue::new()
+    }
+}
+
+impl BitWrite for BitQueue {
+    fn write_bits(&mut self, value: u64, bits: u8) -> Result<()> {
+        raw::check_bit_count(bits, 64)?;
+        self.reserve_bits(bits as usize)?;
+        raw::write_bits(&mut self.buf, self.write_pos, value, bits, self.order);
+        self.write_pos += bits as usize;
+        Ok(())
+    }
+
+    fn reserve_bits(&mut self, bits: usize) -> Result<()> {
+        let end = match self.write_pos.checked_add(bits) {
+            Some(end) => end,
+            None => return Err(BitBufError::OutOfBounds { needed: bits, remaining: 0 }),
+        };
+        if end.div_ceil(8) > self.buf.len() {
+            self.buf.resize(end.div_ceil(8), 0);
+        }
+        Ok(())
+    }
+
+    fn bit_pos(&self) -> usize {
+        self.write_pos
+    }
+
+    fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
+    }
+
+    fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    fn write_u8_slice(&mut self, value: &[u8]) -> Result<()> {
+        self.reserve_bits(value.len().saturating_mul(8))?;
+        raw::write_bytes(&mut self.buf, self.write_pos, value, self.order);
+        self.write_pos += value.len() * 8;
+        Ok(())
+    }
+}
+
+impl BitRead for BitQueue {
+    fn read_bits(&mut self, bits: u8) -> Result<u64> {
+        raw::check_bit_count(bits, 64)?;
+        if !self.can_read_bits(bits as usize) {
+            return Err(BitBufError::OutOfBounds {
+                needed: bits as usize,
+                remaining: self.readable_bits(),
+            });
+        }
+        let value = raw::read_bits(&self.buf, self.read_pos, bits, self.order);
+        self.read_pos += bits as usize;
+        Ok(value)
+    }
+
+    fn remaining_bits(&self) -> usize {
+        self.readable_bits()
+    }
+
+    fn bit_pos(&self) -> usize {
+        self.read_pos
+    }
+
+    /// Moves the read cursor, which may not pass the write cursor.
+    fn set_bit_pos(&mut self, pos: usize) -> Result<()> {
+        if pos > self.write_pos {
+            return Err(BitBufError::OutOfBounds {
+                needed: pos - self.read_pos,
+                remaining: self.readable_bits(),
+            });
+        }
+        self.read_pos = pos;
+        Ok(())
+    }
+
+    fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
+    }
+
+    fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    fn read_into(&mut self, out: &mut [u8]) -> Result<()> {
+        let bits = out.len().saturating_mul(8);
+        if !self.can_read_bits(bits) {
+            return Err(BitBufError::OutOfBounds {
+                needed: bits,
+                remaining: self.readable_bits(),
+            });
+        }
+        raw::read_bytes(&self.buf, self.read_pos, out, self.order);
+        self.read_pos += bits;
+        Ok(())
+    }
+}
+
+#[test]
+fn queue_streams_messages() {
+    let mut queue = BitQueue::new();
+    queue.write_u8_part(5, 3).unwrap();
+    queue.write_string("first").unwrap();
+    assert!(queue.readable_bits() == 3 + 32 + 5 * 8);
+
+    assert!(queue.read_u8_part(3).unwrap() == 5);
+    queue.write_i32_part(-1000, 13).unwrap();
+    assert!(queue.read_string().unwrap() == "first");
+    queue.write_string("second").unwrap();
+
+    let unread = queue.readable_bits();
+    queue.compact();
+    assert!(queue.readable_bits() == unread);
+    assert!(queue.read_pos() == 3);
+    assert!(queue.read_i32_part(13).unwrap() == -1000);
+    assert!(queue.read_string().unwrap() == "second");
+    assert!(queue.is_empty());
+    assert!(queue.read_bool().is_err());
+
+    queue.compact();
+    assert!(queue.write_pos() == 0);
+}
+
+#[test]
+fn queue_read_stops_at_write_cursor() {
+    let mut queue = BitQueue::with_capacity(16);
+    queue.write_u16(0xBEEF).unwrap();
+    assert!(queue.read_u32() == Err(BitBufError::OutOfBounds { needed: 32, remaining: 16 }));
+    assert!(queue.set_bit_pos(17).is_err());
+    assert!(queue.read_u16().unwrap() == 0xBEEF);
+}
+
+#[test]
+fn queue_alignment_per_cursor() {
+    let mut queue = BitQueue::new();
+    queue.write_u8_part(5, 3).unwrap();
+    assert!(queue.is_byte_aligned() && !queue.is_write_aligned());
+    queue.pad_to_byte().unwrap();
+    assert!(queue.is_write_aligned() && queue.write_pos() == 8);
+    queue.read_bool().unwrap();
+    assert!(!queue.is_byte_aligned());
+    queue.align_to_byte().unwrap();
+    assert!(queue.is_byte_aligned() && queue.is_empty());
+}
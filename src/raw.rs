@@ -0,0 +1,441 @@
+//! Bit level helpers shared by the buffer types. The functions that
+//! access byte slices do not check bounds; callers must do so first.
+
+use std::convert::TryInto;
+use std::str;
+
+use error::{BitBufError, Result};
+
+/// The order in which bits are packed into the underlying bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Each byte fills from its least significant bit, and values are
+    /// written least significant bit first. Multi-byte values land in
+    /// little-endian order. This is the default.
+    #[default]
+    LsbFirst,
+    /// Each byte fills from its most significant bit, and values are
+    /// written most significant bit first, as in network headers.
+    /// Multi-byte values land in big-endian order.
+    MsbFirst,
+}
+
+/// How the length of a string, byte array or collection is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LengthPrefix {
+    /// An unsigned integer of 1 to 64 bits.
+    Fixed(u8),
+    /// A LEB128 varint: 8 bits per 7 bits of length, so short lengths
+    /// stay short.
+    Varint,
+}
+
+impl LengthPrefix {
+    pub const U8: LengthPrefix = LengthPrefix::Fixed(8);
+    pub const U16: LengthPrefix = LengthPrefix::Fixed(16);
+    /// The default.
+    pub const U32: LengthPrefix = LengthPrefix::Fixed(32);
+
+    /// Checks that a fixed width is between 1 and 64 bits.
+    pub(crate) fn check(self) -> Result<()> {
+        match self {
+            LengthPrefix::Fixed(0) => Err(BitBufError::InvalidBitCount { bits: 0, max: 64 }),
+            LengthPrefix::Fixed(bits) => check_bit_count(bits, 64),
+            LengthPrefix::Varint => Ok(()),
+        }
+    }
+
+    /// The number of bits the prefix for `len` takes, or `None` if a
+    /// fixed width is too narrow to hold it.
+    #[inline]
+    pub(crate) fn bits(self, len: usize) -> Option<usize> {
+        match self {
+            LengthPrefix::Fixed(bits) if bits < 64 && (len as u64) >> bits != 0 => None,
+            LengthPrefix::Fixed(bits) => Some(bits as usize),
+            LengthPrefix::Varint => Some(var_bits(len as u64, 7)),
+        }
+    }
+}
+
+impl Default for LengthPrefix {
+    fn default() -> LengthPrefix {
+        LengthPrefix::U32
+    }
+}
+
+/// Checks that `bits` is no larger than `max`.
+#[inline]
+pub fn check_bit_count(bits: u8, max: u8) -> Result<()> {
+    if bits <= max {
+        Ok(())
+    } else {
+        Err(BitBufError::InvalidBitCount { bits, max })
+    }
+}
+
+/// Checks that `chunk_bits` is a usable varint chunk width, 1 to 31.
+pub fn check_chunk_bits(chunk_bits: u8) -> Result<()> {
+    if chunk_bits == 0 {
+        return Err(BitBufError::InvalidBitCount { bits: 0, max: 31 });
+    }
+    check_bit_count(chunk_bits, 31)
+}
+
+/// The varint chunk width of delta encoded values. A change of up to
+/// 7 either way costs 6 bits: the changed bit and one 5-bit chunk.
+pub const DELTA_CHUNK_BITS: u8 = 4;
+
+/// The number of bits `value` takes as a varint in `chunk_bits` chunks,
+/// each followed by a continuation bit.
+pub fn var_bits(value: u64, chunk_bits: u8) -> usize {
+    let value_bits = 64 - value.leading_zeros() as usize;
+    value_bits.div_ceil(chunk_bits as usize).max(1) * (chunk_bits as usize + 1)
+}
+
+/// The number of bits from `pos` to the next multiple of `align`. An
+/// alignment of 0 or 1 needs no padding.
+pub fn padding_bits(pos: usize, align: usize) -> usize {
+    if align <= 1 {
+        return 0;
+    }
+    (align - pos % align) % align
+}
+
+/// The `len` bytes at bit `pos` as a string, for reads that borrow
+/// rather than copy. Fails if they run past `size` bits, do not start on
+/// a byte boundary, or are not UTF-8.
+pub fn str_at(buf: &[u8], pos: usize, size: usize, len: usize) -> Result<&str> {
+    if len > (size - pos) / 8 {
+        return Err(BitBufError::InvalidLength(len));
+    }
+    if pos & 0x7 != 0 {
+        return Err(BitBufError::NotByteAligned);
+    }
+    let p = pos / 8;
+    str::from_utf8(&buf[p..p + len]).map_err(BitBufError::InvalidUtf8)
+}
+
+/// Maps signed values to unsigned ones so that small magnitudes of
+/// either sign become small numbers: 0, -1, 1, -2 map to 0, 1, 2, 3.
+pub fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Sign-extends the low `bits` bits of `value`.
+#[inline]
+pub fn sign_extend(value: u64, bits: u8) -> i64 {
+    if bits == 0 {
+        return 0;
+    }
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+/// Checks that `value` fits in `bits` bits of two's complement, so that
+/// sign-extending them gives it back. Only 0 fits in no bits.
+#[inline]
+pub fn check_signed_fits(value: i64, bits: u8) -> Result<()> {
+    if sign_extend(value as u64, bits) == value {
+        Ok(())
+    } else {
+        Err(BitBufError::ValueOutOfRange)
+    }
+}
+
+/// Checks the arguments shared by the quantized float methods.
+pub fn check_quantize(min: f32, max: f32, bits: u8) -> Result<()> {
+    if bits == 0 {
+        return Err(BitBufError::InvalidBitCount { bits, max: 32 });
+    }
+    check_bit_count(bits, 32)?;
+    if !(min.is_finite() && max.is_finite() && min < max) {
+        return Err(BitBufError::ValueOutOfRange);
+    }
+    Ok(())
+}
+
+/// Maps `value` onto `0..=2^bits - 1`, clamping it to `min..=max` and
+/// rounding to the nearest step. NaN maps to `min`.
+pub fn quantize(value: f32, min: f32, max: f32, bits: u8) -> Result<u32> {
+    check_quantize(min, max, bits)?;
+    let steps = (u32::MAX >> (32 - bits)) as f64;
+    let value = if value.is_nan() { min } else { value.max(min).min(max) };
+    let normalized = (value as f64 - min as f64) / (max as f64 - min as f64);
+    Ok((normalized * steps).round() as u32)
+}
+
+/// The inverse of `quantize`.
+pub fn dequantize(quantized: u32, min: f32, max: f32, bits: u8) -> Result<f32> {
+    check_quantize(min, max, bits)?;
+    let steps = (u32::MAX >> (32 - bits)) as f64;
+    let value = min as f64 + (quantized as f64 / steps) * (max as f64 - min as f64);
+    Ok(value.max(min as f64).min(max as f64) as f32)
+}
+
+/// The fewest bits that give steps no wider than `resolution` across
+/// `min..=max`.
+pub fn resolution_bits(min: f32, max: f32, resolution: f32) -> Result<u8> {
+    if !(resolution > 0.0 && resolution.is_finite() && min.is_finite() && max.is_finite() && min < max) {
+        return Err(BitBufError::ValueOutOfRange);
+    }
+    let steps = ((max as f64 - min as f64) / resolution as f64).ceil();
+    if steps > u32::MAX as f64 {
+        return Err(BitBufError::InvalidBitCount { bits: 33, max: 32 });
+    }
+    Ok((64 - (steps as u64).leading_zeros()).max(1) as u8)
+}
+
+/// Writes the low `bits` (at most 64) bits of `value` at bit `pos`.
+///
+/// Whole bytes on a byte boundary are copied in, and bits that fall
+/// within one byte update just that byte. Anything else loads the bytes
+/// under the cursor as one unaligned u64 word, updates it with a mask
+/// and stores it back, so a write touches memory at most twice no
+/// matter how wide it is.
+#[inline]
+pub fn write_bits(buf: &mut [u8], pos: usize, value: u64, bits: u8, order: BitOrder) {
+    if bits == 0 {
+        return;
+    }
+    let p = pos >> 3;
+    let offset = (pos & 0x7) as u32;
+    let bits = bits as u32;
+    let value = value & ones(bits);
+    if offset == 0 && bits & 0x7 == 0 {
+        let n = (bits >> 3) as usize;
+        match order {
+            BitOrder::LsbFirst => buf[p..p + n].copy_from_slice(&value.to_le_bytes()[..n]),
+            BitOrder::MsbFirst => buf[p..p + n].copy_from_slice(&value.to_be_bytes()[8 - n..]),
+        }
+        return;
+    }
+    if offset + bits <= 8 {
+        let shift = match order {
+            BitOrder::LsbFirst => offset,
+            BitOrder::MsbFirst => 8 - offset - bits,
+        };
+        let mask = (ones(bits) as u8) << shift;
+        buf[p] = (buf[p] & !mask) | ((value as u8) << shift);
+        return;
+    }
+    write_word(buf, p, offset, value, bits, order);
+}
+
+/// The general case of `write_bits`, kept out of line so that the fast
+/// paths inline into typed writes.
+fn write_word(buf: &mut [u8], p: usize, offset: u32, value: u64, bits: u32, order: BitOrder) {
+    // Bits that spill past the 64-bit word into the ninth byte.
+    let spill = (offset + bits).saturating_sub(64);
+
+    match order {
+        BitOrder::LsbFirst => {
+            let word = u64::from_le_bytes(load(buf, p));
+            let mask = ones(bits - spill) << offset;
+            store(buf, p, ((word & !mask) | (value << offset)).to_le_bytes());
+            if spill > 0 {
+                let mask = ones(spill) as u8;
+                buf[p + 8] = (buf[p + 8] & !mask) | (value >> (64 - offset)) as u8;
+            }
+        },
+        BitOrder::MsbFirst => {
+            let word = u64::from_be_bytes(load(buf, p));
+            let shift = 64 - offset - (bits - spill);
+            let mask = ones(bits - spill) << shift;
+            store(buf, p, ((word & !mask) | ((value >> spill) << shift)).to_be_bytes());
+            if spill > 0 {
+                let shift = 8 - spill;
+                let mask = (ones(spill) as u8) << shift;
+                buf[p + 8] = (buf[p + 8] & !mask) | ((value as u8) << shift);
+            }
+        },
+    }
+}
+
+/// Reads `bits` (at most 64) bits at bit `pos` written by `write_bits`.
+#[inline]
+pub fn read_bits(buf: &[u8], pos: usize, bits: u8, order: BitOrder) -> u64 {
+    if bits == 0 {
+        return 0;
+    }
+    let p = pos >> 3;
+    let offset = (pos & 0x7) as u32;
+    let bits = bits as u32;
+    if offset + bits <= 8 {
+        let shift = match order {
+            BitOrder::LsbFirst => offset,
+            BitOrder::MsbFirst => 8 - offset - bits,
+        };
+        return (buf[p] >> shift) as u64 & ones(bits);
+    }
+    read_word(buf, p, offset, bits, order)
+}
+
+/// The general case of `read_bits`, kept out of line as `write_word` is.
+fn read_word(buf: &[u8], p: usize, offset: u32, bits: u32, order: BitOrder) -> u64 {
+    let spill = (offset + bits).saturating_sub(64);
+
+    match order {
+        BitOrder::LsbFirst => {
+            let mut value = u64::from_le_bytes(load(buf, p)) >> offset;
+            if spill > 0 {
+                value |= (buf[p + 8] as u64) << (64 - offset);
+            }
+            value & ones(bits)
+        },
+        BitOrder::MsbFirst => {
+            let mut value = (u64::from_be_bytes(load(buf, p)) << offset) >> (64 - bits);
+            if spill > 0 {
+                value |= (buf[p + 8] >> (8 - spill)) as u64;
+            }
+            value
+        },
+    }
+}
+
+/// Writes `bytes` at bit `pos`, as if each was written with
+/// `write_bits(.., 8, order)`. Aligned writes are a plain copy; unaligned
+/// ones go through `write_bits` a word at a time, the last few bytes
+/// together.
+#[inline]
+pub fn write_bytes(buf: &mut [u8], pos: usize, bytes: &[u8], order: BitOrder) {
+    if pos & 0x7 == 0 {
+        let p = pos >> 3;
+        buf[p..p + bytes.len()].copy_from_slice(bytes);
+        return;
+    }
+    let mut pos = pos;
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        let word = match order {
+            BitOrder::LsbFirst => u64::from_le_bytes(chunk.try_into().unwrap()),
+            BitOrder::MsbFirst => u64::from_be_bytes(chunk.try_into().unwrap()),
+        };
+        write_bits(buf, pos, word, 64, order);
+        pos += 64;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        let mut bytes = [0; 8];
+        bytes[..rest.len()].copy_from_slice(rest);
+        let word = match order {
+            BitOrder::LsbFirst => u64::from_le_bytes(bytes),
+            BitOrder::MsbFirst => u64::from_be_bytes(bytes) >> (64 - rest.len() * 8),
+        };
+        write_bits(buf, pos, word, (rest.len() * 8) as u8, order);
+    }
+}
+
+/// Fills `out` with the bytes at bit `pos` written by `write_bytes`.
+pub fn read_bytes(buf: &[u8], pos: usize, out: &mut [u8], order: BitOrder) {
+    if pos & 0x7 == 0 {
+        let p = pos >> 3;
+        out.copy_from_slice(&buf[p..p + out.len()]);
+        return;
+    }
+    let mut pos = pos;
+    let mut chunks = out.chunks_exact_mut(8);
+    for chunk in &mut chunks {
+        let word = read_bits(buf, pos, 64, order);
+        chunk.copy_from_slice(&match order {
+            BitOrder::LsbFirst => word.to_le_bytes(),
+            BitOrder::MsbFirst => word.to_be_bytes(),
+        });
+        pos += 64;
+    }
+    for byte in chunks.into_remainder() {
+        *byte = read_bits(buf, pos, 8, order) as u8;
+        pos += 8;
+    }
+}
+
+/// A mask of the low `bits` bits, for `bits` up to 64.
+#[inline(always)]
+fn ones(bits: u32) -> u64 {
+    if bits >= 64 { !0 } else { (1 << bits) - 1 }
+}
+
+/// Loads the 8 bytes starting at `p`, zero filling past the end.
+#[inline(always)]
+fn load(buf: &[u8], p: usize) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    if buf.len() >= p + 8 {
+        bytes.copy_from_slice(&buf[p..p + 8]);
+    } else {
+        let n = buf.len() - p;
+        bytes[..n].copy_from_slice(&buf[p..]);
+    }
+    bytes
+}
+
+/// Stores `bytes` at `p`, dropping any that fall past the end.
+#[inline(always)]
+fn store(buf: &mut [u8], p: usize, bytes: [u8; 8]) {
+    let n = (buf.len() - p).min(8);
+    buf[p..p + n].copy_from_slice(&bytes[..n]);
+}
+
+#[test]
+fn raw_matches_bitwise_reference() {
+    // Writes one bit at a time, the slow and obvious way.
+    fn reference(buf: &mut [u8], pos: usize, value: u64, bits: u8, order: BitOrder) {
+        for i in 0..bits as usize {
+            let bit = match order {
+                BitOrder::LsbFirst => (value >> i) & 1,
+                BitOrder::MsbFirst => (value >> (bits as usize - 1 - i)) & 1,
+            } as u8;
+            let p = pos + i;
+            let shift = match order {
+                BitOrder::LsbFirst => p & 7,
+                BitOrder::MsbFirst => 7 - (p & 7),
+            };
+            buf[p >> 3] = (buf[p >> 3] & !(1 << shift)) | (bit << shift);
+        }
+    }
+
+    let mut state = 0x2545F4914F6CDD1Du64;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+        for len in 1..20 {
+            for _ in 0..200 {
+                let mut expected: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+                let mut actual = expected.clone();
+                let bits = (next() % 65).min(len as u64 * 8) as u8;
+                let pos = (next() % (len as u64 * 8 - bits as u64 + 1)) as usize;
+                let value = next();
+                reference(&mut expected, pos, value, bits, order);
+                write_bits(&mut actual, pos, value, bits, order);
+                assert!(actual == expected);
+                let mask = if bits == 64 { !0 } else { (1u64 << bits) - 1 };
+                assert!(read_bits(&actual, pos, bits, order) == value & mask);
+            }
+        }
+    }
+}
+
+#[test]
+fn raw_bytes_match_bytewise_writes() {
+    let bytes: Vec<u8> = (0..37).map(|i| (i * 73 + 11) as u8).collect();
+    for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+        for pos in 0..16 {
+            let mut expected = vec![0xA5; 40];
+            let mut actual = expected.clone();
+            for (i, &byte) in bytes.iter().enumerate() {
+                write_bits(&mut expected, pos + i * 8, byte as u64, 8, order);
+            }
+            write_bytes(&mut actual, pos, &bytes, order);
+            assert!(actual == expected);
+            let mut out = vec![0; bytes.len()];
+            read_bytes(&actual, pos, &mut out, order);
+            assert!(out == bytes);
+        }
+    }
+}
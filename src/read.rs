@@ -0,0 +1,559 @@
+use std::io::SeekFrom;
+use std::mem;
+
+use error::{BitBufError, Result};
+use raw::{self, check_bit_count, LengthPrefix};
+use bits_required;
+
+/// A source of bits. Implementors provide the cursor primitives and
+/// get the typed read methods for free.
+pub trait BitRead {
+    /// Reads `bits` (at most 64) bits in the reader's bit order into the
+    /// low bits of the result. On error the cursor does not move.
+    fn read_bits(&mut self, bits: u8) -> Result<u64>;
+
+    /// The number of bits left to read.
+    fn remaining_bits(&self) -> usize;
+
+    /// The current bit position of the cursor.
+    fn bit_pos(&self) -> usize;
+
+    /// Moves the cursor to bit `pos`, which may be at most the end.
+    fn set_bit_pos(&mut self, pos: usize) -> Result<()>;
+
+    /// How lengths of strings, byte arrays and collections are read.
+    /// Must match the writer's. Defaults to `LengthPrefix::U32`.
+    fn length_prefix(&self) -> LengthPrefix {
+        LengthPrefix::default()
+    }
+
+    /// The longest length `read_len` accepts. Defaults to no limit.
+    fn max_len(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Reads a length written by `BitWrite::write_len`. Lengths above
+    /// `max_len()` are rejected before the caller allocates anything,
+    /// and the cursor is left unchanged.
+    fn read_len(&mut self) -> Result<usize> {
+        let start = self.bit_pos();
+        let len = match self.length_prefix() {
+            LengthPrefix::Fixed(bits) => self.read_u64_part(bits),
+            LengthPrefix::Varint => self.read_var_u64(),
+        };
+        let len = match len {
+            Ok(len) if len <= self.max_len() as u64 => return Ok(len as usize),
+            Ok(len) => Err(BitBufError::InvalidLength(len.min(usize::MAX as u64) as usize)),
+            Err(err) => Err(err),
+        };
+        self.set_bit_pos(start)?;
+        len
+    }
+
+    /// Moves the cursor like `std::io::Seek`, but in bits, and returns
+    /// the new position. `SeekFrom::End` is relative to the end of the
+    /// readable bits, `bit_pos() + remaining_bits()`: for a `BitBuf` that
+    /// is its size, not the furthest bit written. Seeking before the
+    /// start or past the end fails without moving.
+    fn seek_bits(&mut self, pos: SeekFrom) -> Result<usize> {
+        let current = self.bit_pos();
+        let end = current + self.remaining_bits();
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (0, offset as i128),
+            SeekFrom::End(offset) => (end, offset as i128),
+            SeekFrom::Current(offset) => (current, offset as i128),
+        };
+        let target = base as i128 + offset;
+        if target < 0 {
+            return Err(BitBufError::OutOfBounds {
+                needed: (current as i128 - target) as usize,
+                remaining: current,
+            });
+        }
+        if target > end as i128 {
+            return Err(BitBufError::OutOfBounds {
+                needed: (target - current as i128) as usize,
+                remaining: self.remaining_bits(),
+            });
+        }
+        self.set_bit_pos(target as usize)?;
+        Ok(target as usize)
+    }
+
+    /// Moves the cursor back to the start.
+    fn rewind(&mut self) -> Result<()> {
+        self.set_bit_pos(0)
+    }
+
+    /// Moves the cursor forward `bits` bits without reading them.
+    fn skip_bits(&mut self, bits: usize) -> Result<()> {
+        if !self.can_read_bits(bits) {
+            return Err(BitBufError::OutOfBounds {
+                needed: bits,
+                remaining: self.remaining_bits(),
+            });
+        }
+        let pos = self.bit_pos() + bits;
+        self.set_bit_pos(pos)
+    }
+
+    fn skip_bytes(&mut self, bytes: usize) -> Result<()> {
+        self.skip_bits(bytes.saturating_mul(8))
+    }
+
+    /// Whether the read cursor is on a byte boundary. The writing side
+    /// is `BitWrite::is_write_aligned`.
+    fn is_byte_aligned(&self) -> bool {
+        self.bit_pos() & 0x7 == 0
+    }
+
+    /// Skips the read cursor to the next byte boundary. To write zero
+    /// padding instead, use `BitWrite::pad_to_byte`.
+    fn align_to_byte(&mut self) -> Result<()> {
+        self.align_to(8)
+    }
+
+    /// Skips to the next multiple of `bits`, the reading side of
+    /// `BitWrite::pad_to`.
+    fn align_to(&mut self, bits: usize) -> Result<()> {
+        let padding = raw::padding_bits(self.bit_pos(), bits);
+        self.skip_bits(padding)
+    }
+
+    /// Skips to the next byte boundary, failing with `InvalidPadding`
+    /// if any skipped bit is set.
+    fn align_to_byte_checked(&mut self) -> Result<()> {
+        self.align_to_checked(8)
+    }
+
+    /// Like `align_to`, but fails with `InvalidPadding` if any skipped
+    /// bit is set. The cursor does not move on error.
+    fn align_to_checked(&mut self, bits: usize) -> Result<()> {
+        let start = self.bit_pos();
+        let mut padding = raw::padding_bits(start, bits);
+        if !self.can_read_bits(padding) {
+            return Err(BitBufError::OutOfBounds {
+                needed: padding,
+                remaining: self.remaining_bits(),
+            });
+        }
+        while padding > 0 {
+            let chunk = padding.min(64);
+            if self.read_bits(chunk as u8)? != 0 {
+                self.set_bit_pos(start)?;
+                return Err(BitBufError::InvalidPadding);
+            }
+            padding -= chunk;
+        }
+        Ok(())
+    }
+
+    fn can_read_bits(&self, bit_count: usize) -> bool {
+        bit_count <= self.remaining_bits()
+    }
+
+    fn can_read_bytes(&self, byte_count: usize) -> bool {
+        byte_count <= self.remaining_bits() / 8
+    }
+
+    /// Reads `bits` (at most 64) bits like `read_bits`, then moves the
+    /// cursor back so the next read sees the same bits.
+    fn peek_bits(&mut self, bits: u8) -> Result<u64> {
+        let pos = self.bit_pos();
+        let value = self.read_bits(bits)?;
+        self.set_bit_pos(pos)?;
+        Ok(value)
+    }
+
+    fn peek_bool(&mut self) -> Result<bool> {
+        Ok(self.peek_bits(1)? == 1)
+    }
+
+    fn peek_u8_part(&mut self, bits: u8) -> Result<u8> {
+        check_bit_count(bits, 8)?;
+        Ok(self.peek_bits(bits)? as u8)
+    }
+
+    fn peek_u32_part(&mut self, bits: u8) -> Result<u32> {
+        check_bit_count(bits, 32)?;
+        Ok(self.peek_bits(bits)? as u32)
+    }
+
+    fn peek_u64_part(&mut self, bits: u8) -> Result<u64> {
+        self.peek_bits(bits)
+    }
+
+    fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    fn read_i8(&mut self) -> Result<i8> {
+        self.read_i8_part(8)
+    }
+
+    /// Reads a value written by `write_i8_part`, sign-extending it from
+    /// `bits` bits.
+    fn read_i8_part(&mut self, bits: u8) -> Result<i8> {
+        check_bit_count(bits, 8)?;
+        Ok(raw::sign_extend(self.read_bits(bits)?, bits) as i8)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        self.read_u8_part(8)
+    }
+
+    fn read_u8_part(&mut self, bits: u8) -> Result<u8> {
+        check_bit_count(bits, 8)?;
+        Ok(self.read_bits(bits)? as u8)
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        self.read_u16_part(16)
+    }
+
+    fn read_u16_part(&mut self, bits: u8) -> Result<u16> {
+        check_bit_count(bits, 16)?;
+        Ok(self.read_bits(bits)? as u16)
+    }
+
+    fn read_i16(&mut self) -> Result<i16> {
+        self.read_i16_part(16)
+    }
+
+    fn read_i16_part(&mut self, bits: u8) -> Result<i16> {
+        check_bit_count(bits, 16)?;
+        Ok(raw::sign_extend(self.read_bits(bits)?, bits) as i16)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        self.read_u32_part(32)
+    }
+
+    fn read_u32_part(&mut self, bits: u8) -> Result<u32> {
+        check_bit_count(bits, 32)?;
+        Ok(self.read_bits(bits)? as u32)
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        self.read_i32_part(32)
+    }
+
+    fn read_i32_part(&mut self, bits: u8) -> Result<i32> {
+        check_bit_count(bits, 32)?;
+        Ok(raw::sign_extend(self.read_bits(bits)?, bits) as i32)
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        self.read_u64_part(64)
+    }
+
+    fn read_u64_part(&mut self, bits: u8) -> Result<u64> {
+        self.read_bits(bits)
+    }
+
+    fn read_i64(&mut self) -> Result<i64> {
+        self.read_i64_part(64)
+    }
+
+    fn read_i64_part(&mut self, bits: u8) -> Result<i64> {
+        Ok(raw::sign_extend(self.read_bits(bits)?, bits))
+    }
+
+    /// Reads a value written by `write_ranged_u32` with the same range.
+    /// Decoded values above `max` are rejected.
+    fn read_ranged_u32(&mut self, min: u32, max: u32) -> Result<u32> {
+        Ok(self.read_ranged_u64(min as u64, max as u64)? as u32)
+    }
+
+    fn read_ranged_u64(&mut self, min: u64, max: u64) -> Result<u64> {
+        if min > max {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        let offset = self.read_u64_part(bits_required(max - min))?;
+        if offset > max - min {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        Ok(min + offset)
+    }
+
+    fn read_ranged_i32(&mut self, min: i32, max: i32) -> Result<i32> {
+        Ok(self.read_ranged_i64(min as i64, max as i64)? as i32)
+    }
+
+    fn read_ranged_i64(&mut self, min: i64, max: i64) -> Result<i64> {
+        if min > max {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        let span = max.wrapping_sub(min) as u64;
+        let offset = self.read_u64_part(bits_required(span))?;
+        if offset > span {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        Ok(min.wrapping_add(offset as i64))
+    }
+
+    /// Reads a value written by `write_i32_sign_magnitude`.
+    fn read_i32_sign_magnitude(&mut self, bits: u8) -> Result<i32> {
+        check_bit_count(bits, 32)?;
+        Ok(self.read_i64_sign_magnitude(bits)? as i32)
+    }
+
+    /// Reads a value written by `write_i64_sign_magnitude`. Negative
+    /// zero reads as zero.
+    fn read_i64_sign_magnitude(&mut self, bits: u8) -> Result<i64> {
+        if bits == 0 {
+            return Err(BitBufError::InvalidBitCount { bits, max: 64 });
+        }
+        let raw = self.read_bits(bits)?;
+        let mask = if bits == 1 { 0 } else { u64::MAX >> (65 - bits) };
+        let magnitude = (raw & mask) as i64;
+        if raw >> (bits - 1) == 1 {
+            Ok(-magnitude)
+        } else {
+            Ok(magnitude)
+        }
+    }
+
+    fn read_f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    fn read_f64(&mut self) -> Result<f64> {
+        Ok(f64::from_bits(self.read_u64()?))
+    }
+
+    /// Reads a value written by `write_var_u32`. On error, including a
+    /// value cut off by the end of the data, the cursor does not move.
+    fn read_var_u32(&mut self) -> Result<u32> {
+        self.read_var_u32_chunked(7)
+    }
+
+    fn read_var_u64(&mut self) -> Result<u64> {
+        self.read_var_u64_chunked(7)
+    }
+
+    fn read_var_i32(&mut self) -> Result<i32> {
+        let value = raw::zigzag_decode(self.read_var_u32()? as u64);
+        Ok(value as i32)
+    }
+
+    fn read_var_i64(&mut self) -> Result<i64> {
+        Ok(raw::zigzag_decode(self.read_var_u64()?))
+    }
+
+    /// Reads a value written by `write_var_u32_chunked` with the same
+    /// `chunk_bits`. Values that overflow a u32 are rejected.
+    fn read_var_u32_chunked(&mut self, chunk_bits: u8) -> Result<u32> {
+        let start = self.bit_pos();
+        let value = self.read_var_u64_chunked(chunk_bits)?;
+        if value > u32::MAX as u64 {
+            self.set_bit_pos(start)?;
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        Ok(value as u32)
+    }
+
+    fn read_var_u64_chunked(&mut self, chunk_bits: u8) -> Result<u64> {
+        let start = self.bit_pos();
+        let value = read_var_chunks(self, chunk_bits);
+        if value.is_err() {
+            self.set_bit_pos(start)?;
+        }
+        value
+    }
+
+    /// Reads a value written by `write_f32_quantized` with the same
+    /// `min`, `max` and `bits`.
+    fn read_f32_quantized(&mut self, min: f32, max: f32, bits: u8) -> Result<f32> {
+        raw::check_quantize(min, max, bits)?;
+        raw::dequantize(self.read_u32_part(bits)?, min, max, bits)
+    }
+
+    /// Reads a value written by `write_f32_resolution` with the same
+    /// `min`, `max` and `resolution`.
+    fn read_f32_resolution(&mut self, min: f32, max: f32, resolution: f32) -> Result<f32> {
+        let bits = raw::resolution_bits(min, max, resolution)?;
+        self.read_f32_quantized(min, max, bits)
+    }
+
+    /// Fills `out` with the next `out.len()` bytes without allocating.
+    /// Nothing is read if there are not enough bytes left.
+    fn read_into(&mut self, out: &mut [u8]) -> Result<()> {
+        if !self.can_read_bytes(out.len()) {
+            return Err(BitBufError::OutOfBounds {
+                needed: out.len().saturating_mul(8),
+                remaining: self.remaining_bits(),
+            });
+        }
+        for byte in out {
+            *byte = self.read_bits(8)? as u8;
+        }
+        Ok(())
+    }
+
+    fn read_vec_u8(&mut self, length: usize) -> Result<Vec<u8>> {
+        if !self.can_read_bytes(length) {
+            return Err(BitBufError::OutOfBounds {
+                needed: length.saturating_mul(8),
+                remaining: self.remaining_bits(),
+            });
+        }
+        let mut vec = vec![0; length];
+        self.read_into(&mut vec)?;
+        Ok(vec)
+    }
+
+    /// Reads a value written by `write_delta_u8` against `baseline`. On
+    /// error the cursor does not move.
+    fn read_delta_u8(&mut self, baseline: u8) -> Result<u8> {
+        Ok(read_delta_ranged(self, baseline as i64, u8::MIN as i64, u8::MAX as i64)? as u8)
+    }
+
+    fn read_delta_u16(&mut self, baseline: u16) -> Result<u16> {
+        Ok(read_delta_ranged(self, baseline as i64, u16::MIN as i64, u16::MAX as i64)? as u16)
+    }
+
+    fn read_delta_u32(&mut self, baseline: u32) -> Result<u32> {
+        Ok(read_delta_ranged(self, baseline as i64, u32::MIN as i64, u32::MAX as i64)? as u32)
+    }
+
+    fn read_delta_u64(&mut self, baseline: u64) -> Result<u64> {
+        Ok(self.read_delta_i64(baseline as i64)? as u64)
+    }
+
+    fn read_delta_i8(&mut self, baseline: i8) -> Result<i8> {
+        Ok(read_delta_ranged(self, baseline as i64, i8::MIN as i64, i8::MAX as i64)? as i8)
+    }
+
+    fn read_delta_i16(&mut self, baseline: i16) -> Result<i16> {
+        Ok(read_delta_ranged(self, baseline as i64, i16::MIN as i64, i16::MAX as i64)? as i16)
+    }
+
+    fn read_delta_i32(&mut self, baseline: i32) -> Result<i32> {
+        Ok(read_delta_ranged(self, baseline as i64, i32::MIN as i64, i32::MAX as i64)? as i32)
+    }
+
+    fn read_delta_i64(&mut self, baseline: i64) -> Result<i64> {
+        let start = self.bit_pos();
+        if !self.read_bool()? {
+            return Ok(baseline);
+        }
+        match self.read_var_u64_chunked(raw::DELTA_CHUNK_BITS) {
+            Ok(delta) => Ok(baseline.wrapping_add(raw::zigzag_decode(delta))),
+            Err(err) => {
+                self.set_bit_pos(start)?;
+                Err(err)
+            },
+        }
+    }
+
+    fn read_delta_f32(&mut self, baseline: f32) -> Result<f32> {
+        Ok(f32::from_bits(self.read_delta_u32(baseline.to_bits())?))
+    }
+
+    fn read_delta_f64(&mut self, baseline: f64) -> Result<f64> {
+        Ok(f64::from_bits(self.read_delta_u64(baseline.to_bits())?))
+    }
+
+    /// Reads a byte array written by `BitWrite::write_bytes`. The cursor
+    /// is left unchanged if the length is invalid.
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let start = self.bit_pos();
+        let len = self.read_len()?;
+        if !self.can_read_bytes(len) {
+            self.set_bit_pos(start)?;
+            return Err(BitBufError::InvalidLength(len));
+        }
+        self.read_vec_u8(len)
+    }
+
+    /// Reads a string written by `write_string`. The cursor is left
+    /// unchanged if the length prefix or the UTF-8 data is invalid.
+    fn read_string(&mut self) -> Result<String> {
+        let start = self.bit_pos();
+        match String::from_utf8(self.read_bytes()?) {
+            Ok(string) => Ok(string),
+            Err(err) => {
+                self.set_bit_pos(start)?;
+                Err(BitBufError::InvalidUtf8(err.utf8_error()))
+            },
+        }
+    }
+
+    /// Reads a string written by `write_string`, replacing invalid UTF-8
+    /// with U+FFFD rather than failing. Meant for logging untrusted data.
+    fn read_string_lossy(&mut self) -> Result<String> {
+        let bytes = self.read_bytes()?;
+        match String::from_utf8(bytes) {
+            Ok(string) => Ok(string),
+            Err(err) => Ok(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+        }
+    }
+
+    /// Reads a string written by `write_string` into `out`, reusing its
+    /// allocation. On error `out` is left empty and the cursor unchanged.
+    fn read_string_into(&mut self, out: &mut String) -> Result<()> {
+        out.clear();
+        let start = self.bit_pos();
+        let len = self.read_len()?;
+        if !self.can_read_bytes(len) {
+            self.set_bit_pos(start)?;
+            return Err(BitBufError::InvalidLength(len));
+        }
+        let mut bytes = mem::take(out).into_bytes();
+        bytes.resize(len, 0);
+        self.read_into(&mut bytes)?;
+        match String::from_utf8(bytes) {
+            Ok(string) => {
+                *out = string;
+                Ok(())
+            },
+            Err(err) => {
+                let utf8_error = err.utf8_error();
+                let mut bytes = err.into_bytes();
+                bytes.clear();
+                *out = String::from_utf8(bytes).unwrap_or_default();
+                self.set_bit_pos(start)?;
+                Err(BitBufError::InvalidUtf8(utf8_error))
+            },
+        }
+    }
+}
+
+/// Reads the chunks of a varint, leaving the cursor wherever it stopped.
+fn read_var_chunks<R: BitRead + ?Sized>(buf: &mut R, chunk_bits: u8) -> Result<u64> {
+    raw::check_chunk_bits(chunk_bits)?;
+    let mut value = 0u64;
+    let mut shift = 0u32;
+    loop {
+        let chunk = buf.read_u32_part(chunk_bits + 1)? as u64;
+        let data = chunk & ((1 << chunk_bits) - 1);
+        if shift + chunk_bits as u32 > 64 && data >> (64 - shift) != 0 {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        value |= data << shift;
+        if chunk >> chunk_bits == 0 {
+            return Ok(value);
+        }
+        shift += chunk_bits as u32;
+        if shift >= 64 {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+    }
+}
+
+/// Reads a delta against `baseline`, rejecting results outside
+/// `min..=max`. Deltas of narrow types are written without wrapping.
+/// On error the cursor does not move.
+fn read_delta_ranged<R: BitRead + ?Sized>(buf: &mut R, baseline: i64, min: i64, max: i64) -> Result<i64> {
+    let start = buf.bit_pos();
+    let value = match buf.read_delta_i64(0) {
+        Ok(delta) => match baseline.checked_add(delta) {
+            Some(value) if value >= min && value <= max => return Ok(value),
+            _ => Err(BitBufError::ValueOutOfRange),
+        },
+        Err(err) => Err(err),
+    };
+    buf.set_bit_pos(start)?;
+    value
+}
@@ -0,0 +1,229 @@
+use checksum;
+use error::{BitBufError, Result};
+use raw::{self, BitOrder, LengthPrefix};
+use read::BitRead;
+
+/// A read-only cursor over borrowed bytes. Decoding from a `BitReader`
+/// avoids copying a received packet into a `BitBuf`.
+#[derive(Clone, Copy, Debug)]
+pub struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,       // The current bit position of the cursor.
+    size: usize,      // Size in bits.
+    order: BitOrder,
+    length_prefix: LengthPrefix,
+    max_len: usize,   // The longest length accepted.
+}
+
+impl<'a> BitReader<'a> {
+
+    /// Creates a new BitReader positioned at the start of `buf`.
+    pub fn new(buf: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            buf,
+            pos: 0,
+            size: buf.len() * 8,
+            order: BitOrder::LsbFirst,
+            length_prefix: LengthPrefix::U32,
+            max_len: usize::MAX,
+        }
+    }
+
+    /// Creates a new BitReader over `buf` that reads in `order`.
+    pub fn with_bit_order(buf: &'a [u8], order: BitOrder) -> BitReader<'a> {
+        BitReader { order, ..BitReader::new(buf) }
+    }
+
+    /// The order bits are unpacked in. Defaults to `BitOrder::LsbFirst`.
+    pub fn bit_order(&self) -> BitOrder {
+        self.order
+    }
+
+    /// Changes the order that following reads use.
+    pub fn set_bit_order(&mut self, order: BitOrder) {
+        self.order = order;
+    }
+
+    /// Changes how following reads expect lengths to be prefixed, as
+    /// `BitBuf::set_length_prefix`. It must match the writer's.
+    pub fn set_length_prefix(&mut self, prefix: LengthPrefix) -> Result<()> {
+        prefix.check()?;
+        self.length_prefix = prefix;
+        Ok(())
+    }
+
+    /// Changes the longest length accepted. Longer lengths are rejected
+    /// before anything is allocated.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// Returns the underlying byte slice.
+    pub fn get_ref(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    /// The bit size of the underlying slice.
+    pub fn bit_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn byte_pos(&self) -> usize {
+        self.pos / 8
+    }
+
+    /// Checks the CRC-32 that `BitBuf::append_crc32` left in the last
+    /// four bytes, ignoring the cursor.
+    pub fn verify_crc32(&self) -> Result<bool> {
+        checksum::verify_crc32(self.buf, self.size, self.order)
+    }
+
+    /// Reads a string written by `write_string` without copying it. The
+    /// result borrows from the underlying slice rather than the reader,
+    /// so it outlives the cursor. The string data must start on a byte
+    /// boundary. On error the cursor does not move.
+    pub fn read_str(&mut self) -> Result<&'a str> {
+        let start = self.pos;
+        let len = self.read_len()?;
+        match raw::str_at(self.buf, self.pos, self.size, len) {
+            Ok(string) => {
+                self.pos += len * 8;
+                Ok(string)
+            },
+            Err(err) => {
+                self.pos = start;
+                Err(err)
+            },
+        }
+    }
+}
+
+impl<'a> BitRead for BitReader<'a> {
+    fn read_bits(&mut self, bits: u8) -> Result<u64> {
+        raw::check_bit_count(bits, 64)?;
+        if !self.can_read_bits(bits as usize) {
+            return Err(BitBufError::OutOfBounds {
+                needed: bits as usize,
+                remaining: self.remaining_bits(),
+            });
+        }
+        let value = raw::read_bits(self.buf, self.pos, bits, self.order);
+        self.pos += bits as usize;
+        Ok(value)
+    }
+
+    fn remaining_bits(&self) -> usize {
+        self.size - self.pos
+    }
+
+    fn bit_pos(&self) -> usize {
+        self.pos
+    }
+
+    fn length_prefix(&self) -> LengthPrefix {
+        self.length_prefix
+    }
+
+    fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    fn set_bit_pos(&mut self, pos: usize) -> Result<()> {
+        if pos > self.size {
+            return Err(BitBufError::OutOfBounds {
+                needed: pos - self.pos,
+                remaining: self.remaining_bits(),
+            });
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn read_into(&mut self, out: &mut [u8]) -> Result<()> {
+        let bits = out.len().saturating_mul(8);
+        if !self.can_read_bits(bits) {
+            return Err(BitBufError::OutOfBounds {
+                needed: bits,
+                remaining: self.remaining_bits(),
+            });
+        }
+        raw::read_bytes(self.buf, self.pos, out, self.order);
+        self.pos += bits;
+        Ok(())
+    }
+}
+
+#[test]
+fn reader_reads_bitbuf_output() {
+    use {BitBuf, BitWrite};
+
+    let mut buf = BitBuf::new();
+    buf.write_bool(true).unwrap();
+    buf.write_u32_part(839011, 27).unwrap();
+    buf.write_string("Borrowed, not copied.").unwrap();
+    buf.write_f64(3.03958312394853f64).unwrap();
+    let bytes = buf.finish();
+
+    let mut reader = BitReader::new(&bytes);
+    assert!(reader.read_bool().unwrap());
+    assert!(reader.read_u32_part(27).unwrap() == 839011);
+    assert!(reader.read_string().unwrap() == "Borrowed, not copied.");
+    assert!(reader.read_f64().unwrap() == 3.03958312394853f64);
+    assert!(reader.read_u8().is_err());
+}
+
+#[test]
+fn reader_decodes_frombitbuf() {
+    use {BitBuf, BitWrite, FromBitBuf};
+
+    struct Point {
+        x: i16,
+        y: i16,
+    }
+
+    impl FromBitBuf for Point {
+        fn from_bitbuf<R: BitRead>(buf: &mut R) -> Result<Point> {
+            Ok(Point {
+                x: buf.read_i16()?,
+                y: buf.read_i16()?,
+            })
+        }
+    }
+
+    let mut buf = BitBuf::with_len(4);
+    buf.write_i16(-11066).unwrap();
+    buf.write_i16(448).unwrap();
+    let bytes = buf.to_vec();
+
+    let point = Point::from_bitbuf(&mut BitReader::new(&bytes)).unwrap();
+    assert!(point.x == -11066 && point.y == 448);
+    assert!(Point::from_bitbuf(&mut BitReader::new(&bytes[..3])).is_err());
+}
+
+#[test]
+fn reader_read_str_outlives_cursor() {
+    use {BitBuf, BitWrite};
+
+    let mut buf = BitBuf::new();
+    buf.write_string("Zero copy.").unwrap();
+    let bytes = buf.finish();
+
+    let name = {
+        let mut reader = BitReader::new(&bytes);
+        reader.read_str().unwrap()
+    };
+    assert!(name == "Zero copy.");
+    assert!(BitReader::new(&bytes[..8]).read_str() == Err(BitBufError::InvalidLength(10)));
+}
+
+#[test]
+fn reader_msb_first_header() {
+    // An IPv4 header's first 32 bits: version 4, IHL 5, DSCP 46, ECN 0, length 1400.
+    let bytes = [0x45, 0xB8, 0x05, 0x78];
+    let mut reader = BitReader::with_bit_order(&bytes, BitOrder::MsbFirst);
+    assert!(reader.read_u8_part(4).unwrap() == 4);
+    assert!(reader.read_u8_part(4).unwrap() == 5);
+    assert!(reader.read_u8_part(6).unwrap() == 46);
+    assert!(reader.read_u8_part(2).unwrap() == 0);
+    assert!(reader.read_u16().unwrap() == 1400);
+}
@@ -0,0 +1,298 @@
+use std::fmt::Display;
+
+use serde::ser::{self, Serialize};
+
+use config::Config;
+use error::{BitBufError, Result};
+use write::BitWrite;
+use BitBuf;
+
+/// Serializes `value` into a new growable BitBuf using the default
+/// configuration. The cursor is left at the end of the written data.
+pub fn to_bitbuf<T: ?Sized + Serialize>(value: &T) -> Result<BitBuf> {
+    to_bitbuf_with(value, Config::default())
+}
+
+/// Serializes `value` into a new growable BitBuf using `config`.
+pub fn to_bitbuf_with<T: ?Sized + Serialize>(value: &T, config: Config) -> Result<BitBuf> {
+    let mut buf = BitBuf::new();
+    value.serialize(&mut Serializer::new(&mut buf, config))?;
+    Ok(buf)
+}
+
+impl ser::Error for BitBufError {
+    fn custom<T: Display>(msg: T) -> BitBufError {
+        BitBufError::Custom(msg.to_string())
+    }
+}
+
+/// A serde Serializer that writes to any `BitWrite` sink at its cursor.
+///
+/// Struct fields and tuple elements are written in order with no
+/// names or separators. Enum variants are written as their index, and
+/// `Option` as a single presence bit followed by the value.
+pub struct Serializer<'a, W: 'a> {
+    buf: &'a mut W,
+    config: Config,
+}
+
+impl<'a, W: BitWrite> Serializer<'a, W> {
+    pub fn new(buf: &'a mut W, config: Config) -> Serializer<'a, W> {
+        Serializer { buf, config }
+    }
+
+    fn write_variant(&mut self, index: u32) -> Result<()> {
+        self.config.write_uint(self.buf, index as u64, 32)
+    }
+}
+
+impl<'a, 'b, W: BitWrite> ser::Serializer for &'b mut Serializer<'a, W> {
+    type Ok = ();
+    type Error = BitBufError;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.buf.write_bool(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.config.write_sint(self.buf, v as i64, 8)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.config.write_sint(self.buf, v as i64, 16)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.config.write_sint(self.buf, v as i64, 32)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.config.write_sint(self.buf, v, 64)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.config.write_uint(self.buf, v as u64, 8)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.config.write_uint(self.buf, v as u64, 16)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.config.write_uint(self.buf, v as u64, 32)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.config.write_uint(self.buf, v, 64)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.buf.write_f32(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.buf.write_f64(v)
+    }
+
+    /// Chars take 21 bits, enough for any Unicode scalar value.
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.buf.write_u32_part(v as u32, 21)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.config.lengths(self.buf)?.write_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.buf.write_bool(false)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<()> {
+        self.buf.write_bool(true)?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, variant_index: u32, _variant: &'static str) -> Result<()> {
+        self.write_variant(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.write_variant(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self> {
+        let len = len.ok_or_else(|| BitBufError::Custom(String::from("sequence length must be known")))?;
+        self.config.lengths(self.buf)?.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        self.write_variant(variant_index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self> {
+        let len = len.ok_or_else(|| BitBufError::Custom(String::from("map length must be known")))?;
+        self.config.lengths(self.buf)?.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        self.write_variant(variant_index)?;
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'a, 'b, W: BitWrite> ser::SerializeSeq for &'b mut Serializer<'a, W> {
+    type Ok = ();
+    type Error = BitBufError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b, W: BitWrite> ser::SerializeTuple for &'b mut Serializer<'a, W> {
+    type Ok = ();
+    type Error = BitBufError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b, W: BitWrite> ser::SerializeTupleStruct for &'b mut Serializer<'a, W> {
+    type Ok = ();
+    type Error = BitBufError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b, W: BitWrite> ser::SerializeTupleVariant for &'b mut Serializer<'a, W> {
+    type Ok = ();
+    type Error = BitBufError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b, W: BitWrite> ser::SerializeMap for &'b mut Serializer<'a, W> {
+    type Ok = ();
+    type Error = BitBufError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b, W: BitWrite> ser::SerializeStruct for &'b mut Serializer<'a, W> {
+    type Ok = ();
+    type Error = BitBufError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b, W: BitWrite> ser::SerializeStructVariant for &'b mut Serializer<'a, W> {
+    type Ok = ();
+    type Error = BitBufError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
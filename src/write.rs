@@ -0,0 +1,394 @@
+use error::{BitBufError, Result};
+use raw::{self, check_bit_count, LengthPrefix};
+use bits_required;
+
+/// A sink for bits. Implementors provide the cursor primitives and
+/// get the typed write methods for free.
+pub trait BitWrite {
+    /// Writes the low `bits` (at most 64) bits of `value`, ignoring the
+    /// rest. On error nothing is written.
+    fn write_bits(&mut self, value: u64, bits: u8) -> Result<()>;
+
+    /// Checks that `bits` more bits can be written, growing the sink if
+    /// it can. Writes made of several parts call this first so that they
+    /// either fit whole or write nothing.
+    fn reserve_bits(&mut self, bits: usize) -> Result<()>;
+
+    /// The current bit position of the cursor.
+    fn bit_pos(&self) -> usize;
+
+    /// Whether the write cursor is on a byte boundary, so that
+    /// `pad_to_byte` would write nothing. Named apart from
+    /// `BitRead::is_byte_aligned` for types with separate cursors.
+    fn is_write_aligned(&self) -> bool {
+        self.bit_pos() & 0x7 == 0
+    }
+
+    /// Writes zero bits up to the next byte boundary. The writing side
+    /// of `BitRead::align_to_byte`, named apart so both can be called on
+    /// one type.
+    fn pad_to_byte(&mut self) -> Result<()> {
+        self.pad_to(8)
+    }
+
+    /// Writes zero bits up to the next multiple of `bits`, such as 32 to
+    /// reach a word boundary. Nothing is written if they do not all fit.
+    fn pad_to(&mut self, bits: usize) -> Result<()> {
+        let mut padding = raw::padding_bits(self.bit_pos(), bits);
+        self.reserve_bits(padding)?;
+        while padding > 0 {
+            let chunk = padding.min(64);
+            self.write_bits(0, chunk as u8)?;
+            padding -= chunk;
+        }
+        Ok(())
+    }
+
+    /// How lengths of strings, byte arrays and collections are written.
+    /// Defaults to `LengthPrefix::U32`.
+    fn length_prefix(&self) -> LengthPrefix {
+        LengthPrefix::default()
+    }
+
+    /// The longest length `write_len` accepts. Defaults to no limit.
+    fn max_len(&self) -> usize {
+        usize::MAX
+    }
+
+    /// The number of bits `write_len` would write for `len`. Lengths
+    /// above `max_len()` or too wide for the prefix are rejected.
+    fn len_bits(&self, len: usize) -> Result<usize> {
+        if len > self.max_len() {
+            return Err(BitBufError::InvalidLength(len));
+        }
+        self.length_prefix().bits(len).ok_or(BitBufError::InvalidLength(len))
+    }
+
+    /// Writes a length with `length_prefix()`.
+    fn write_len(&mut self, len: usize) -> Result<()> {
+        self.len_bits(len)?;
+        write_checked_len(self, len)
+    }
+
+    fn write_bool(&mut self, value: bool) -> Result<()> {
+        self.write_u8_part(value as u8, 1)
+    }
+
+    fn write_i8(&mut self, value: i8) -> Result<()> {
+        self.write_i8_part(value, 8)
+    }
+
+    /// Writes `value` in `bits` bits of two's complement. Read it back
+    /// with `read_i8_part` to sign-extend it. Unlike the unsigned `_part`
+    /// writes, values that do not fit are rejected with `ValueOutOfRange`.
+    fn write_i8_part(&mut self, value: i8, bits: u8) -> Result<()> {
+        check_bit_count(bits, 8)?;
+        raw::check_signed_fits(value as i64, bits)?;
+        self.write_u8_part(value as u8, bits)
+    }
+
+    fn write_u8(&mut self, value: u8) -> Result<()> {
+        self.write_u8_part(value, 8)
+    }
+
+    fn write_u8_part(&mut self, value: u8, bits: u8) -> Result<()> {
+        check_bit_count(bits, 8)?;
+        self.write_bits(value as u64, bits)
+    }
+
+    fn write_u16(&mut self, value: u16) -> Result<()> {
+        self.write_u16_part(value, 16)
+    }
+
+    fn write_u16_part(&mut self, value: u16, bits: u8) -> Result<()> {
+        check_bit_count(bits, 16)?;
+        self.write_bits(value as u64, bits)
+    }
+
+    fn write_i16(&mut self, value: i16) -> Result<()> {
+        self.write_i16_part(value, 16)
+    }
+
+    fn write_i16_part(&mut self, value: i16, bits: u8) -> Result<()> {
+        check_bit_count(bits, 16)?;
+        raw::check_signed_fits(value as i64, bits)?;
+        self.write_u16_part(value as u16, bits)
+    }
+
+    fn write_u32(&mut self, value: u32) -> Result<()> {
+        self.write_u32_part(value, 32)
+    }
+
+    fn write_u32_part(&mut self, value: u32, bits: u8) -> Result<()> {
+        check_bit_count(bits, 32)?;
+        self.write_bits(value as u64, bits)
+    }
+
+    fn write_i32(&mut self, value: i32) -> Result<()> {
+        self.write_i32_part(value, 32)
+    }
+
+    fn write_i32_part(&mut self, value: i32, bits: u8) -> Result<()> {
+        check_bit_count(bits, 32)?;
+        raw::check_signed_fits(value as i64, bits)?;
+        self.write_u32_part(value as u32, bits)
+    }
+
+    fn write_u64(&mut self, value: u64) -> Result<()> {
+        self.write_u64_part(value, 64)
+    }
+
+    fn write_u64_part(&mut self, value: u64, bits: u8) -> Result<()> {
+        self.write_bits(value, bits)
+    }
+
+    fn write_i64(&mut self, value: i64) -> Result<()> {
+        self.write_i64_part(value, 64)
+    }
+
+    fn write_i64_part(&mut self, value: i64, bits: u8) -> Result<()> {
+        check_bit_count(bits, 64)?;
+        raw::check_signed_fits(value, bits)?;
+        self.write_u64_part(value as u64, bits)
+    }
+
+    /// Writes `value` as an offset from `min`, in `bits_required(max - min)`
+    /// bits. Values outside `min..=max` are rejected.
+    fn write_ranged_u32(&mut self, value: u32, min: u32, max: u32) -> Result<()> {
+        self.write_ranged_u64(value as u64, min as u64, max as u64)
+    }
+
+    fn write_ranged_u64(&mut self, value: u64, min: u64, max: u64) -> Result<()> {
+        if min > max || value < min || value > max {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        self.write_u64_part(value - min, bits_required(max - min))
+    }
+
+    fn write_ranged_i32(&mut self, value: i32, min: i32, max: i32) -> Result<()> {
+        self.write_ranged_i64(value as i64, min as i64, max as i64)
+    }
+
+    fn write_ranged_i64(&mut self, value: i64, min: i64, max: i64) -> Result<()> {
+        if min > max || value < min || value > max {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        let span = max.wrapping_sub(min) as u64;
+        self.write_u64_part(value.wrapping_sub(min) as u64, bits_required(span))
+    }
+
+    /// Writes `value` in `bits` bits (1 to 32) as a magnitude followed by a
+    /// sign bit, the most significant bit of the field. Values whose
+    /// magnitude needs more than `bits - 1` bits are rejected.
+    fn write_i32_sign_magnitude(&mut self, value: i32, bits: u8) -> Result<()> {
+        if bits == 0 {
+            return Err(BitBufError::InvalidBitCount { bits, max: 32 });
+        }
+        check_bit_count(bits, 32)?;
+        self.write_i64_sign_magnitude(value as i64, bits)
+    }
+
+    /// Writes `value` in `bits` bits (1 to 64) as for `write_i32_sign_magnitude`.
+    fn write_i64_sign_magnitude(&mut self, value: i64, bits: u8) -> Result<()> {
+        if bits == 0 {
+            return Err(BitBufError::InvalidBitCount { bits, max: 64 });
+        }
+        check_bit_count(bits, 64)?;
+        let magnitude = value.unsigned_abs();
+        if magnitude >> (bits - 1) != 0 {
+            return Err(BitBufError::ValueOutOfRange);
+        }
+        let sign = ((value < 0) as u64) << (bits - 1);
+        self.write_u64_part(sign | magnitude, bits)
+    }
+
+    fn write_f32(&mut self, value: f32) -> Result<()> {
+        self.write_u32(value.to_bits())
+    }
+
+    fn write_f64(&mut self, value: f64) -> Result<()> {
+        self.write_u64(value.to_bits())
+    }
+
+    /// Writes `value` in `bits` bits (1 to 32) as one of `2^bits` evenly
+    /// spaced steps from `min` to `max`, both of which are exact. Values
+    /// outside the range are clamped to it, NaN is written as `min`, and
+    /// anything else is rounded to the nearest step.
+    fn write_f32_quantized(&mut self, value: f32, min: f32, max: f32, bits: u8) -> Result<()> {
+        let quantized = raw::quantize(value, min, max, bits)?;
+        self.write_u32_part(quantized, bits)
+    }
+
+    /// Writes `value` quantized to the fewest bits that keep it within
+    /// `resolution / 2` of the original, after clamping to `min..=max`.
+    fn write_f32_resolution(&mut self, value: f32, min: f32, max: f32, resolution: f32) -> Result<()> {
+        let bits = raw::resolution_bits(min, max, resolution)?;
+        self.write_f32_quantized(value, min, max, bits)
+    }
+
+    fn write_u8_slice(&mut self, value: &[u8]) -> Result<()> {
+        self.reserve_bits(value.len().saturating_mul(8))?;
+        for &byte in value {
+            self.write_bits(byte as u64, 8)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `value` as LEB128: 7 bits at a time, low bits first, each
+    /// group followed by a bit that is set if more groups follow. When
+    /// byte aligned and `LsbFirst` this matches the usual LEB128 bytes.
+    fn write_var_u32(&mut self, value: u32) -> Result<()> {
+        self.write_var_u64_chunked(value as u64, 7)
+    }
+
+    fn write_var_u64(&mut self, value: u64) -> Result<()> {
+        self.write_var_u64_chunked(value, 7)
+    }
+
+    /// Writes `value` zigzag encoded, so small negative numbers stay short.
+    fn write_var_i32(&mut self, value: i32) -> Result<()> {
+        self.write_var_u32(raw::zigzag_encode(value as i64) as u32)
+    }
+
+    fn write_var_i64(&mut self, value: i64) -> Result<()> {
+        self.write_var_u64(raw::zigzag_encode(value))
+    }
+
+    /// Writes `value` in groups of `chunk_bits` (1 to 31) bits, each
+    /// followed by a continuation bit. Narrow chunks suit values that
+    /// are usually tiny. Nothing is written if the whole value does not fit.
+    fn write_var_u32_chunked(&mut self, value: u32, chunk_bits: u8) -> Result<()> {
+        self.write_var_u64_chunked(value as u64, chunk_bits)
+    }
+
+    fn write_var_u64_chunked(&mut self, mut value: u64, chunk_bits: u8) -> Result<()> {
+        raw::check_chunk_bits(chunk_bits)?;
+        self.reserve_bits(raw::var_bits(value, chunk_bits))?;
+        loop {
+            let chunk = (value & ((1 << chunk_bits) - 1)) as u32;
+            value >>= chunk_bits;
+            let more = (value != 0) as u32;
+            self.write_u32_part(chunk | (more << chunk_bits), chunk_bits + 1)?;
+            if more == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Writes a 0 bit if `value` equals `baseline`. Otherwise writes a 1
+    /// bit and the zigzag encoded difference as a varint in 4-bit chunks,
+    /// so small changes cost a few bits. Read it back with the same
+    /// baseline using `read_delta_u8`.
+    fn write_delta_u8(&mut self, value: u8, baseline: u8) -> Result<()> {
+        self.write_delta_i64(value as i64, baseline as i64)
+    }
+
+    fn write_delta_u16(&mut self, value: u16, baseline: u16) -> Result<()> {
+        self.write_delta_i64(value as i64, baseline as i64)
+    }
+
+    fn write_delta_u32(&mut self, value: u32, baseline: u32) -> Result<()> {
+        self.write_delta_i64(value as i64, baseline as i64)
+    }
+
+    /// Like `write_delta_u8`, but the difference wraps around, so a
+    /// change from `u64::MAX` to 0 costs as little as one of 1.
+    fn write_delta_u64(&mut self, value: u64, baseline: u64) -> Result<()> {
+        self.write_delta_i64(value as i64, baseline as i64)
+    }
+
+    fn write_delta_i8(&mut self, value: i8, baseline: i8) -> Result<()> {
+        self.write_delta_i64(value as i64, baseline as i64)
+    }
+
+    fn write_delta_i16(&mut self, value: i16, baseline: i16) -> Result<()> {
+        self.write_delta_i64(value as i64, baseline as i64)
+    }
+
+    fn write_delta_i32(&mut self, value: i32, baseline: i32) -> Result<()> {
+        self.write_delta_i64(value as i64, baseline as i64)
+    }
+
+    fn write_delta_i64(&mut self, value: i64, baseline: i64) -> Result<()> {
+        if value == baseline {
+            return self.write_bool(false);
+        }
+        let delta = raw::zigzag_encode(value.wrapping_sub(baseline));
+        self.reserve_bits(1 + raw::var_bits(delta, raw::DELTA_CHUNK_BITS))?;
+        self.write_bool(true)?;
+        self.write_var_u64_chunked(delta, raw::DELTA_CHUNK_BITS)
+    }
+
+    /// Writes the difference between the bit patterns of `value` and
+    /// `baseline` as for `write_delta_u32`. The encoding is lossless,
+    /// and nearby values of the same sign differ in few bits.
+    fn write_delta_f32(&mut self, value: f32, baseline: f32) -> Result<()> {
+        self.write_delta_u32(value.to_bits(), baseline.to_bits())
+    }
+
+    fn write_delta_f64(&mut self, value: f64, baseline: f64) -> Result<()> {
+        self.write_delta_u64(value.to_bits(), baseline.to_bits())
+    }
+
+    /// Writes a length prefix followed by the bytes. Nothing is written
+    /// if the whole array does not fit.
+    fn write_bytes(&mut self, value: &[u8]) -> Result<()> {
+        let len_bits = self.len_bits(value.len())?;
+        self.reserve_bits(value.len().saturating_mul(8).saturating_add(len_bits))?;
+        write_checked_len(self, value.len())?;
+        self.write_u8_slice(value)
+    }
+
+    /// Writes a length prefix followed by the UTF-8 bytes of the string.
+    /// Nothing is written if the whole string does not fit.
+    fn write_string(&mut self, value: &str) -> Result<()> {
+        self.write_bytes(value.as_bytes())
+    }
+}
+
+/// Writes a length that `len_bits` has already accepted.
+fn write_checked_len<W: BitWrite + ?Sized>(sink: &mut W, len: usize) -> Result<()> {
+    match sink.length_prefix() {
+        LengthPrefix::Fixed(bits) => sink.write_u64_part(len as u64, bits),
+        LengthPrefix::Varint => sink.write_var_u64(len as u64),
+    }
+}
+
+#[test]
+fn custom_sink_matches_bitbuf() {
+    use {BitBuf, BitRead};
+
+    // Collects bits one at a time, first written first.
+    struct Bits(Vec<bool>);
+
+    impl BitWrite for Bits {
+        fn write_bits(&mut self, value: u64, bits: u8) -> Result<()> {
+            check_bit_count(bits, 64)?;
+            for i in 0..bits {
+                self.0.push((value >> i) & 1 == 1);
+            }
+            Ok(())
+        }
+
+        fn reserve_bits(&mut self, _bits: usize) -> Result<()> {
+            Ok(())
+        }
+
+        fn bit_pos(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    let mut bits = Bits(Vec::new());
+    let mut buf = BitBuf::new();
+    for sink in [&mut bits as &mut dyn BitWrite, &mut buf] {
+        sink.write_i32_part(-54397, 22).unwrap();
+        sink.write_string("Any sink will do.").unwrap();
+        sink.write_var_u64(300).unwrap();
+    }
+    assert!(bits.bit_pos() == BitWrite::bit_pos(&buf));
+    buf.rewind().unwrap();
+    for &bit in &bits.0 {
+        assert!(buf.read_bool().unwrap() == bit);
+    }
+}
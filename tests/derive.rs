@@ -0,0 +1,127 @@
+// Uses bitbuf-derive directly so these run without the `derive` feature.
+#[allow(unused_imports)]
+#[macro_use]
+extern crate bitbuf_derive;
+extern crate bitbuf;
+
+use bitbuf::{BitBuf, BitBufError, BitRead, BitReader, BitWrite, FromBitBuf, WriteToBitBuf};
+
+#[derive(WriteToBitBuf, FromBitBuf, Debug, PartialEq, Clone)]
+struct Person {
+    first_name: String,
+    last_name: String,
+    #[bitbuf(bits = 7)]
+    age: u8,
+    alive: bool,
+    #[bitbuf(range = "-500..=500")]
+    weight: i16,
+    #[bitbuf(bits = 12)]
+    balance: i32,
+    #[bitbuf(skip)]
+    cached_hash: u64,
+}
+
+#[derive(WriteToBitBuf, FromBitBuf, Debug, PartialEq)]
+struct Position(f32, f32, #[bitbuf(range = "0..360")] u16);
+
+#[derive(WriteToBitBuf, FromBitBuf, Debug, PartialEq)]
+enum Command {
+    Stop,
+    Move(Position),
+    Say { #[bitbuf(bits = 4)] channel: u8, text: String },
+}
+
+#[derive(WriteToBitBuf, FromBitBuf, Debug, PartialEq)]
+struct Wrapper<T> {
+    inner: T,
+}
+
+fn roundtrip<T: WriteToBitBuf + FromBitBuf>(value: &T) -> (T, usize) {
+    let mut buf = BitBuf::new();
+    value.write_to_bitbuf(&mut buf).unwrap();
+    let bits = buf.bit_pos();
+    assert!(value.bit_len().unwrap() == bits);
+    let bytes = buf.finish();
+    (T::from_bitbuf(&mut BitReader::new(&bytes)).unwrap(), bits)
+}
+
+#[test]
+fn struct_roundtrip_equal() {
+    let person = Person {
+        first_name: String::from("John"),
+        last_name: String::from("Johnson"),
+        age: 47,
+        alive: true,
+        weight: -203,
+        balance: -2000,
+        cached_hash: 12,
+    };
+    let (result, bits) = roundtrip(&person);
+    assert!(result == Person { cached_hash: 0, ..person });
+    assert!(bits == (32 + 4 * 8) + (32 + 7 * 8) + 7 + 1 + 10 + 12);
+}
+
+#[test]
+fn enum_roundtrip_equal() {
+    let commands = vec![
+        Command::Stop,
+        Command::Move(Position(1.5, -2.25, 359)),
+        Command::Say { channel: 9, text: String::from("hello") },
+    ];
+    for command in &commands {
+        assert!(roundtrip(command).0 == *command);
+    }
+    assert!(roundtrip(&Command::Stop).1 == 2);
+}
+
+#[test]
+fn generic_roundtrip_equal() {
+    let wrapper = Wrapper { inner: Command::Move(Position(0.0, 8.0, 90)) };
+    assert!(roundtrip(&wrapper).0 == wrapper);
+}
+
+#[test]
+fn out_of_range_write_errors() {
+    let mut buf = BitBuf::new();
+    let position = Position(0.0, 0.0, 360);
+    assert!(position.write_to_bitbuf(&mut buf) == Err(BitBufError::ValueOutOfRange));
+}
+
+#[test]
+fn out_of_range_read_errors() {
+    let mut buf = BitBuf::new();
+    buf.write_u8_part(3, 2).unwrap();
+    buf.write_u32_part(1000, 10).unwrap();
+    let bytes = buf.finish();
+    let mut reader = BitReader::new(&bytes);
+    assert!(Command::from_bitbuf(&mut reader) == Err(BitBufError::ValueOutOfRange));
+    reader.set_bit_pos(2).unwrap();
+    assert!(Position::from_bitbuf(&mut reader).is_err());
+}
+
+#[test]
+fn struct_delta_roundtrip_equal() {
+    let baseline = Person {
+        first_name: String::from("John"),
+        last_name: String::from("Johnson"),
+        age: 47,
+        alive: true,
+        weight: -203,
+        balance: -2000,
+        cached_hash: 12,
+    };
+    let person = Person { age: 48, balance: -1990, cached_hash: 0, ..baseline.clone() };
+    let mut buf = BitBuf::new();
+    person.write_delta(&baseline, &mut buf).unwrap();
+    // Unchanged strings, bool and weight cost one bit each; age and
+    // balance a changed bit and their usual widths.
+    assert!(buf.bit_pos() == 1 + 1 + (1 + 7) + 1 + 1 + (1 + 12));
+    buf.rewind().unwrap();
+    assert!(Person::read_delta(&baseline, &mut buf).unwrap() == person);
+
+    let position = Position(1.5, -2.25, 359);
+    let mut buf = BitBuf::new();
+    position.write_delta(&Position(1.5, -2.0, 359), &mut buf).unwrap();
+    buf.rewind().unwrap();
+    assert!(Position::read_delta(&Position(1.5, -2.0, 359), &mut buf).unwrap() == position);
+}
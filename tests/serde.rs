@@ -0,0 +1,124 @@
+#![cfg(feature = "serde")]
+
+extern crate bitbuf;
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::BTreeMap;
+
+use bitbuf::{BitBufError, BitRead, BitReader, Config, IntEncoding, LengthPrefix};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Snapshot {
+    tick: u32,
+    alive: bool,
+    name: String,
+    position: (f32, f32),
+    health: Option<i16>,
+    tags: Vec<char>,
+    scores: BTreeMap<u8, i64>,
+    state: State,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum State {
+    Idle,
+    Moving(f64),
+    Attacking { target: u64, damage: i8 },
+}
+
+fn snapshot() -> Snapshot {
+    let mut scores = BTreeMap::new();
+    scores.insert(1, -40);
+    scores.insert(7, 1 << 40);
+    Snapshot {
+        tick: 193772,
+        alive: true,
+        name: String::from("Jürgen"),
+        position: (1.5, -3.25),
+        health: Some(-12),
+        tags: vec!['a', 'ß', '🦀'],
+        scores,
+        state: State::Attacking { target: 248394023907611, damage: -109 },
+    }
+}
+
+#[test]
+fn struct_roundtrip_equal() {
+    let value = snapshot();
+    let buf = bitbuf::to_bitbuf(&value).unwrap();
+    let bytes = buf.finish();
+    let result: Snapshot = bitbuf::from_bitbuf(&mut BitReader::new(&bytes)).unwrap();
+    assert!(result == value);
+}
+
+#[test]
+fn varint_roundtrip_equal() {
+    let config = Config {
+        int_encoding: IntEncoding::Varint,
+        length_prefix: LengthPrefix::U8,
+        ..Config::default()
+    };
+    let value = snapshot();
+    let fixed = bitbuf::to_bitbuf(&value).unwrap();
+    let mut buf = bitbuf::to_bitbuf_with(&value, config).unwrap();
+    assert!(buf.bit_pos() < fixed.bit_pos());
+    buf.set_bit_pos(0).unwrap();
+    let result: Snapshot = bitbuf::from_bitbuf_with(&mut buf, config).unwrap();
+    assert!(result == value);
+    assert!(buf.remaining_bits() < 8);
+}
+
+#[test]
+fn bool_and_option_take_one_bit() {
+    let buf = bitbuf::to_bitbuf(&(true, None::<u64>, false)).unwrap();
+    assert!(buf.bit_pos() == 3);
+}
+
+#[test]
+fn invalid_variant_errors() {
+    let mut buf = bitbuf::to_bitbuf(&7u32).unwrap();
+    buf.set_bit_pos(0).unwrap();
+    assert!(bitbuf::from_bitbuf::<State, _>(&mut buf).is_err());
+}
+
+#[test]
+fn varint_overflow_errors() {
+    let config = Config { int_encoding: IntEncoding::Varint, ..Config::default() };
+    let mut buf = bitbuf::to_bitbuf_with(&300u16, config).unwrap();
+    buf.set_bit_pos(0).unwrap();
+    assert!(bitbuf::from_bitbuf_with::<u8, _>(&mut buf, config) == Err(BitBufError::ValueOutOfRange));
+}
+
+#[test]
+fn length_prefix_too_small_errors() {
+    let config = Config { length_prefix: LengthPrefix::Fixed(4), ..Config::default() };
+    let result = bitbuf::to_bitbuf_with(&vec![0u8; 16], config);
+    assert!(result.err() == Some(BitBufError::InvalidLength(16)));
+}
+
+#[test]
+fn max_len_errors() {
+    let config = Config { max_len: 8, ..Config::default() };
+    let result = bitbuf::to_bitbuf_with("nine long", config);
+    assert!(result.err() == Some(BitBufError::InvalidLength(9)));
+
+    let mut buf = bitbuf::to_bitbuf(&vec![0u32; 9]).unwrap();
+    buf.set_bit_pos(0).unwrap();
+    let result = bitbuf::from_bitbuf_with::<Vec<u32>, _>(&mut buf, config);
+    assert!(result == Err(BitBufError::InvalidLength(9)));
+    assert!(buf.bit_pos() == 0);
+}
+
+#[test]
+fn hostile_length_errors() {
+    // Four billion `()` elements would read no bits at all.
+    let bytes = u32::MAX.to_le_bytes();
+    let mut reader = BitReader::new(&bytes);
+    let result = bitbuf::from_bitbuf::<Vec<()>, _>(&mut reader);
+    assert!(result == Err(BitBufError::InvalidLength(u32::MAX as usize)));
+    assert!(reader.bit_pos() == 0);
+
+    let mut reader = BitReader::new(&bytes);
+    assert!(bitbuf::from_bitbuf::<Vec<u8>, _>(&mut reader).is_err());
+}